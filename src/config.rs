@@ -0,0 +1,328 @@
+use crate::routing::RouteConfig;
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Options read from the file passed with `--config`, CLI options and environment variables take precedence
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub db_host: Option<String>,
+    pub db_port: Option<u16>,
+    pub db_user: Option<String>,
+    pub db_password: Option<String>,
+    pub db_name: Option<String>,
+    pub interval: Option<u64>,
+    pub timeout: Option<u64>,
+    pub max_backoff: Option<u64>,
+    pub max_report_pages: Option<i64>,
+    pub show_private_message_content: Option<bool>,
+    pub federation_gap_interval: Option<u64>,
+    pub federation_gap_grace: Option<u64>,
+    pub jwt_encryption_key: Option<String>,
+    pub outbox_max_attempts: Option<i32>,
+    pub notify_timeout: Option<u64>,
+    pub notifiers: NotifiersConfig,
+    pub routes: Vec<RouteConfig>,
+}
+
+/// Any number of notifiers per type, each with a unique name
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifiersConfig {
+    pub discord: Vec<DiscordConfig>,
+    pub ntfy: Vec<NtfyConfig>,
+    pub mqtt: Vec<MqttConfig>,
+    pub matrix: Vec<MatrixConfig>,
+    pub telegram: Vec<TelegramConfig>,
+    pub webhook: Vec<WebhookConfig>,
+    pub email: Vec<EmailConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub name: String,
+    pub webhook: String,
+    /// Overrides of the default templates by name
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NtfyConfig {
+    pub name: String,
+    pub host: String,
+    #[serde(default = "default_ntfy_topic")]
+    pub topic: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Overrides of the default templates by name
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+fn default_ntfy_topic() -> String {
+    "lemmy-know".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Topic of each message, `{domain}`, `{community}` and `{kind}` are replaced with those of the notification
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    /// `lemmy-know-{name}` by default
+    pub client_id: Option<String>,
+    #[serde(default = "default_mqtt_qos")]
+    pub qos: u8,
+    #[serde(default)]
+    pub retain: bool,
+    /// Connects with TLS, verifying the broker with `ca_file` or the system's root certificates
+    #[serde(default)]
+    pub tls: bool,
+    pub ca_file: Option<PathBuf>,
+    pub client_cert_file: Option<PathBuf>,
+    pub client_key_file: Option<PathBuf>,
+    /// Retained `online` while connected, `offline` through the last will otherwise
+    #[serde(default = "default_mqtt_availability_topic")]
+    pub availability_topic: String,
+    /// Prefix of the retained open report counts per instance and community
+    #[serde(default = "default_mqtt_open_reports_topic")]
+    pub open_reports_topic: String,
+    /// Prefix of the retained status of the latest poll per instance
+    #[serde(default = "default_mqtt_status_topic")]
+    pub status_topic: String,
+    /// Publishes Home Assistant discovery configs with a device per instance
+    #[serde(default)]
+    pub home_assistant: bool,
+    #[serde(default = "default_mqtt_discovery_prefix")]
+    pub discovery_prefix: String,
+    /// Prefix of the events of the Home Assistant event entities
+    #[serde(default = "default_mqtt_report_events_topic")]
+    pub report_events_topic: String,
+    /// Subscribes to moderation commands on this topic, e.g. `lemmy-know/cmd`
+    pub command_topic: Option<String>,
+    /// `{command_topic}/response` by default
+    pub response_topic: Option<String>,
+    /// Commands are only executed when signed with this secret, required with a command topic
+    pub command_secret: Option<String>,
+}
+
+pub const DEFAULT_MQTT_TOPIC: &str = "lemmy-know/{kind}";
+pub const DEFAULT_MQTT_AVAILABILITY_TOPIC: &str = "lemmy-know/availability";
+pub const DEFAULT_MQTT_OPEN_REPORTS_TOPIC: &str = "lemmy-know/open_reports";
+pub const DEFAULT_MQTT_STATUS_TOPIC: &str = "lemmy-know/status";
+pub const DEFAULT_MQTT_DISCOVERY_PREFIX: &str = "homeassistant";
+pub const DEFAULT_MQTT_REPORT_EVENTS_TOPIC: &str = "lemmy-know/report_events";
+
+fn default_mqtt_topic() -> String {
+    DEFAULT_MQTT_TOPIC.to_string()
+}
+
+fn default_mqtt_availability_topic() -> String {
+    DEFAULT_MQTT_AVAILABILITY_TOPIC.to_string()
+}
+
+fn default_mqtt_open_reports_topic() -> String {
+    DEFAULT_MQTT_OPEN_REPORTS_TOPIC.to_string()
+}
+
+fn default_mqtt_status_topic() -> String {
+    DEFAULT_MQTT_STATUS_TOPIC.to_string()
+}
+
+fn default_mqtt_discovery_prefix() -> String {
+    DEFAULT_MQTT_DISCOVERY_PREFIX.to_string()
+}
+
+fn default_mqtt_report_events_topic() -> String {
+    DEFAULT_MQTT_REPORT_EVENTS_TOPIC.to_string()
+}
+
+fn default_mqtt_qos() -> u8 {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixConfig {
+    pub name: String,
+    /// Base URL of the homeserver, e.g. `https://matrix.org`
+    pub homeserver: String,
+    pub access_token: String,
+    /// Internal room ID, e.g. `!abc:matrix.org`
+    pub room_id: String,
+    /// Sends reports of the same post and their updates in one thread
+    #[serde(default)]
+    pub threads: bool,
+    /// Overrides of the default templates by name
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub name: String,
+    pub bot_token: String,
+    /// Numeric chat ID or `@channelusername`
+    pub chat_id: String,
+    #[serde(default = "default_telegram_api_url")]
+    pub api_url: String,
+    /// Sends buttons to resolve the report or remove its content, requires `allowed_users`
+    #[serde(default = "default_telegram_buttons")]
+    pub buttons: bool,
+    /// Telegram user IDs that may use the buttons
+    #[serde(default)]
+    pub allowed_users: Vec<i64>,
+    /// Overrides of the default templates by name
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+fn default_telegram_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+fn default_telegram_buttons() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub name: String,
+    pub url: String,
+    /// Sent with every request, e.g. for authentication
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Key of the HMAC-SHA256 signature, requests aren't signed without it
+    pub secret: Option<String>,
+    /// Immediate retries of requests that failed with a connection error, a timeout or a 5xx or 429 status
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+    #[serde(default = "default_webhook_content_type")]
+    pub content_type: String,
+    /// Overrides of the default templates by name
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+fn default_webhook_retries() -> u32 {
+    2
+}
+
+fn default_webhook_content_type() -> String {
+    "application/json".to_string()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Upgrades the connection with STARTTLS, which is required
+    #[default]
+    Starttls,
+    /// Implicit TLS
+    Tls,
+    /// Unencrypted, only for local relays
+    None,
+}
+
+impl SmtpSecurity {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            SmtpSecurity::Starttls => 587,
+            SmtpSecurity::Tls => 465,
+            SmtpSecurity::None => 25,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    pub name: String,
+    pub host: String,
+    /// Depends on `security` by default
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Mailbox, e.g. `Lemmy Know <lemmy-know@example.com>`
+    pub from: String,
+    pub to: Vec<String>,
+    /// Sends the notifications of each interval in one mail instead of one mail each
+    pub digest_interval: Option<u64>,
+    /// Overrides of the default templates by name
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config file {}: {e}", path.display()))?;
+        toml::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse config file {}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_notifiers() {
+        let config: Config = toml::from_str(r#"
+            interval = 120
+
+            [[notifiers.discord]]
+            name = "community-a"
+            webhook = "https://discord.com/api/webhooks/1/a"
+
+            [[notifiers.discord]]
+            name = "community-b"
+            webhook = "https://discord.com/api/webhooks/2/b"
+
+            [[notifiers.ntfy]]
+            name = "admins"
+            host = "https://ntfy.sh"
+
+            [[notifiers.email]]
+            name = "senior-mods"
+            host = "smtp.example.com"
+            security = "tls"
+            from = "lemmy-know@example.com"
+            to = ["mod@example.com"]
+
+            [[routes]]
+            notifiers = ["community-a"]
+            communities = ["a@lemmy.world"]
+        "#).unwrap();
+
+        assert_eq!(Some(120), config.interval);
+        assert_eq!(2, config.notifiers.discord.len());
+        assert_eq!("community-b", config.notifiers.discord[1].name);
+        assert_eq!("lemmy-know", config.notifiers.ntfy[0].topic);
+        assert!(config.notifiers.mqtt.is_empty());
+        assert!(config.notifiers.matrix.is_empty());
+        assert_eq!(SmtpSecurity::Tls, config.notifiers.email[0].security);
+        assert_eq!(None, config.notifiers.email[0].digest_interval);
+        assert_eq!(vec!["a@lemmy.world"], config.routes[0].communities);
+        assert!(config.routes[0].kinds.is_empty());
+    }
+
+    #[test]
+    fn reject_unknown_options() {
+        assert!(toml::from_str::<Config>("intervall = 120").is_err());
+    }
+}
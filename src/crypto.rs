@@ -0,0 +1,100 @@
+use anyhow::anyhow;
+use base64::prelude::*;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+const NONCE_LENGTH: usize = 24;
+
+/// Encrypts the JWTs which are stored in the database
+pub struct TokenCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl TokenCipher {
+    /// The key can be any string, it's hashed to get a key of the right length
+    pub fn new(key: &str) -> Self {
+        let key = Sha256::digest(key.as_bytes());
+        TokenCipher {
+            cipher: XChaCha20Poly1305::new(&key),
+        }
+    }
+
+    /// Returns the nonce and ciphertext as base64
+    pub fn encrypt(&self, token: &str) -> anyhow::Result<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher.encrypt(&nonce, token.as_bytes()).map_err(|e| anyhow!("Failed to encrypt token: {e}"))?;
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(ciphertext);
+        Ok(BASE64_STANDARD.encode(encrypted))
+    }
+
+    pub fn decrypt(&self, encrypted: &str) -> anyhow::Result<String> {
+        let encrypted = BASE64_STANDARD.decode(encrypted)?;
+        if encrypted.len() < NONCE_LENGTH {
+            return Err(anyhow!("Encrypted token is too short"));
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+        let token = self.cipher.decrypt(XNonce::from_slice(nonce), ciphertext).map_err(|e| anyhow!("Failed to decrypt token: {e}"))?;
+        Ok(String::from_utf8(token)?)
+    }
+}
+
+/// HMAC-SHA256 of the body as hex, so receivers of webhooks can verify they were sent by us and we can verify MQTT commands
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    to_hex(&mac.finalize().into_bytes())
+}
+
+/// Compares the hex signature with the one of the body in constant time, so it can't be guessed byte by byte
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let expected = sign(secret, body);
+    let signature = signature.to_ascii_lowercase();
+    expected.len() == signature.len() && expected.bytes().zip(signature.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// SHA-256 of the body as hex
+pub fn hash(body: &[u8]) -> String {
+    to_hex(&Sha256::digest(body))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let cipher = TokenCipher::new("secret");
+        let encrypted = cipher.encrypt("jwt").unwrap();
+        assert_ne!("jwt", encrypted);
+        assert_eq!("jwt", cipher.decrypt(&encrypted).unwrap());
+    }
+
+    #[test]
+    fn wrong_key() {
+        let encrypted = TokenCipher::new("secret").encrypt("jwt").unwrap();
+        assert!(TokenCipher::new("other").decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn hmac_signature() {
+        // RFC 4231 test case 2
+        assert_eq!("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843", sign("Jefe", b"what do ya want for nothing?"));
+    }
+
+    #[test]
+    fn verify_signature() {
+        let signature = sign("secret", b"body");
+        assert!(verify("secret", b"body", &signature));
+        assert!(verify("secret", b"body", &signature.to_uppercase()));
+        assert!(!verify("other", b"body", &signature));
+        assert!(!verify("secret", b"other", &signature));
+        assert!(!verify("secret", b"body", ""));
+    }
+}
@@ -1,13 +1,32 @@
 use crate::env::EnvVariables;
 use crate::models::comment_report::CommentReportEntity;
+use crate::models::credential_role::CredentialRoleEntity;
+use crate::models::credential_token::CredentialTokenEntity;
+use crate::models::discord_message::DiscordMessageEntity;
+use crate::models::email_digest_entry::{EmailDigestEntryEntity, NewEmailDigestEntryEntity};
+use crate::models::federation_gap::FederationGap;
+use crate::models::instance_status::InstanceStatus;
+use crate::models::matrix_message::MatrixMessageEntity;
+use crate::models::open_report_count::OpenReportCount;
+use crate::models::outbox_notification::{NewOutboxNotificationEntity, OutboxNotificationEntity};
 use crate::models::post_report::PostReportEntity;
+use crate::models::private_message_report::PrivateMessageReportEntity;
+use crate::models::registration_application::RegistrationApplicationEntity;
+use crate::models::report_history::NewReportHistoryEntity;
+use crate::report::{ReportKind, ReportUpdate, ReportView};
 use crate::stupid;
-use diesel::{sql_query, ExpressionMethods, QueryDsl, SelectableHelper};
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Text};
+use diesel::{sql_query, ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
 use diesel_async::RunQueryDsl;
-use diesel_async::{AsyncConnection, AsyncPgConnection};
-use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView};
+use diesel_async::pooled_connection::deadpool::Pool;
+use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+use diesel_async::AsyncPgConnection;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
 
-pub async fn establish_db_conn(env_vars: &EnvVariables) -> anyhow::Result<AsyncPgConnection> {
+pub type DbPool = Pool<AsyncPgConnection>;
+
+pub fn create_db_pool(env_vars: &EnvVariables) -> anyhow::Result<DbPool> {
     let db_url = format!(
         "postgres://{}:{}@{}:{}/{}",
         env_vars.db_user,
@@ -16,7 +35,8 @@ pub async fn establish_db_conn(env_vars: &EnvVariables) -> anyhow::Result<AsyncP
         env_vars.db_port,
         env_vars.db_name
     );
-    Ok(AsyncPgConnection::establish(&db_url).await?)
+    let manager = AsyncDieselConnectionManager::<AsyncPgConnection>::new(db_url);
+    Ok(Pool::builder(manager).build()?)
 }
 
 pub async fn create_tables(db_conn: &mut AsyncPgConnection) -> anyhow::Result<()> {
@@ -30,6 +50,44 @@ pub async fn create_tables(db_conn: &mut AsyncPgConnection) -> anyhow::Result<()
         );
     "#).execute(db_conn).await?;
 
+    sql_query(r#"
+        ALTER TABLE credentials ADD COLUMN IF NOT EXISTS page_size INTEGER;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE credentials ADD COLUMN IF NOT EXISTS totp_secret TEXT;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE credentials ADD COLUMN IF NOT EXISTS interval_seconds INTEGER;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE credentials ADD COLUMN IF NOT EXISTS timeout_seconds INTEGER;
+    "#).execute(db_conn).await?;
+
+    // Kept apart from the credentials so storing a token doesn't look like a change of credentials
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS credential_tokens
+        (
+            domain   TEXT NOT NULL,
+            username TEXT NOT NULL,
+            jwt      TEXT NOT NULL,
+            CONSTRAINT credential_tokens_pk PRIMARY KEY (domain, username)
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS credential_roles
+        (
+            domain      TEXT    NOT NULL,
+            username    TEXT    NOT NULL,
+            is_admin    BOOLEAN NOT NULL,
+            communities TEXT[]  NOT NULL,
+            CONSTRAINT credential_roles_pk PRIMARY KEY (domain, username)
+        );
+    "#).execute(db_conn).await?;
+
     sql_query(r#"
         CREATE TABLE IF NOT EXISTS post_reports
         (
@@ -50,31 +108,278 @@ pub async fn create_tables(db_conn: &mut AsyncPgConnection) -> anyhow::Result<()
         );
     "#).execute(db_conn).await?;
 
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS private_message_reports
+        (
+            domain TEXT    NOT NULL,
+            id     INTEGER NOT NULL,
+            data   JSONB   NOT NULL,
+            CONSTRAINT private_message_reports_pk PRIMARY KEY (domain, id)
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE post_reports ADD COLUMN IF NOT EXISTS report_key TEXT;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE post_reports ADD COLUMN IF NOT EXISTS first_seen TIMESTAMPTZ NOT NULL DEFAULT now();
+    "#).execute(db_conn).await?;
+
+    // Reports stored before the key was introduced are given one based on their data
+    sql_query(r#"
+        UPDATE post_reports
+        SET report_key = (data -> 'post' ->> 'ap_id') || ' ' || (data -> 'creator' ->> 'actor_id')
+        WHERE report_key IS NULL;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE INDEX IF NOT EXISTS post_reports_report_key_idx ON post_reports (report_key);
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE post_reports ADD COLUMN IF NOT EXISTS gone BOOLEAN NOT NULL DEFAULT FALSE;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE comment_reports ADD COLUMN IF NOT EXISTS report_key TEXT;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE comment_reports ADD COLUMN IF NOT EXISTS first_seen TIMESTAMPTZ NOT NULL DEFAULT now();
+    "#).execute(db_conn).await?;
+
+    // Reports stored before the key was introduced are given one based on their data
+    sql_query(r#"
+        UPDATE comment_reports
+        SET report_key = (data -> 'comment' ->> 'ap_id') || ' ' || (data -> 'creator' ->> 'actor_id')
+        WHERE report_key IS NULL;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE INDEX IF NOT EXISTS comment_reports_report_key_idx ON comment_reports (report_key);
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE comment_reports ADD COLUMN IF NOT EXISTS gone BOOLEAN NOT NULL DEFAULT FALSE;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE private_message_reports ADD COLUMN IF NOT EXISTS report_key TEXT;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE private_message_reports ADD COLUMN IF NOT EXISTS first_seen TIMESTAMPTZ NOT NULL DEFAULT now();
+    "#).execute(db_conn).await?;
+
+    // Reports stored before the key was introduced are given one based on their data
+    sql_query(r#"
+        UPDATE private_message_reports
+        SET report_key = (data -> 'private_message' ->> 'ap_id') || ' ' || (data -> 'creator' ->> 'actor_id')
+        WHERE report_key IS NULL;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE INDEX IF NOT EXISTS private_message_reports_report_key_idx ON private_message_reports (report_key);
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE private_message_reports ADD COLUMN IF NOT EXISTS gone BOOLEAN NOT NULL DEFAULT FALSE;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS registration_applications
+        (
+            domain TEXT    NOT NULL,
+            id     INTEGER NOT NULL,
+            data   JSONB   NOT NULL,
+            CONSTRAINT registration_applications_pk PRIMARY KEY (domain, id)
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS report_history
+        (
+            id          BIGSERIAL   NOT NULL,
+            domain      TEXT        NOT NULL,
+            kind        TEXT        NOT NULL,
+            report_id   INTEGER     NOT NULL,
+            resolved    BOOLEAN     NOT NULL,
+            resolver_id INTEGER,
+            changed_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+            CONSTRAINT report_history_pk PRIMARY KEY (id)
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE report_history ADD COLUMN IF NOT EXISTS content_removed BOOLEAN NOT NULL DEFAULT false;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS discord_messages
+        (
+            domain     TEXT    NOT NULL,
+            kind       TEXT    NOT NULL,
+            report_id  INTEGER NOT NULL,
+            webhook_id TEXT    NOT NULL,
+            message_id TEXT    NOT NULL,
+            CONSTRAINT discord_messages_pk PRIMARY KEY (domain, kind, report_id, webhook_id)
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS matrix_messages
+        (
+            domain      TEXT    NOT NULL,
+            kind        TEXT    NOT NULL,
+            report_id   INTEGER NOT NULL,
+            room_id     TEXT    NOT NULL,
+            post_id     INTEGER,
+            event_id    TEXT    NOT NULL,
+            thread_root TEXT,
+            CONSTRAINT matrix_messages_pk PRIMARY KEY (domain, kind, report_id, room_id)
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS instance_polls
+        (
+            domain       TEXT        NOT NULL,
+            username     TEXT        NOT NULL,
+            last_poll    TIMESTAMPTZ NOT NULL,
+            last_success TIMESTAMPTZ,
+            logged_in    BOOLEAN     NOT NULL,
+            last_error   TEXT,
+            CONSTRAINT instance_polls_pk PRIMARY KEY (domain, username)
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS email_digest_entries
+        (
+            id         BIGSERIAL PRIMARY KEY,
+            notifier   TEXT        NOT NULL,
+            subject    TEXT        NOT NULL,
+            text       TEXT        NOT NULL,
+            html       TEXT        NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE TABLE IF NOT EXISTS notification_outbox
+        (
+            id              BIGSERIAL PRIMARY KEY,
+            notifier        TEXT        NOT NULL,
+            event           TEXT        NOT NULL,
+            source_domain   TEXT,
+            payload         JSONB       NOT NULL,
+            status          TEXT        NOT NULL DEFAULT 'pending',
+            attempts        INTEGER     NOT NULL DEFAULT 0,
+            last_error      TEXT,
+            next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE INDEX IF NOT EXISTS notification_outbox_status_idx ON notification_outbox (status, next_attempt_at);
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        ALTER TABLE notification_outbox ADD COLUMN IF NOT EXISTS report TEXT;
+    "#).execute(db_conn).await?;
+
+    sql_query(r#"
+        CREATE INDEX IF NOT EXISTS notification_outbox_report_idx ON notification_outbox (notifier, report);
+    "#).execute(db_conn).await?;
+
+    Ok(())
+}
+
+pub async fn get_credential_token(db_conn: &mut AsyncPgConnection, token_domain: &str, token_username: &str) -> anyhow::Result<Option<String>> {
+    use crate::schema::credential_tokens::dsl::*;
+    let token: Option<CredentialTokenEntity> = credential_tokens
+        .filter(domain.eq(token_domain))
+        .filter(username.eq(token_username))
+        .select(CredentialTokenEntity::as_select())
+        .first(db_conn)
+        .await
+        .optional()?;
+
+    Ok(token.map(|t| t.jwt))
+}
+
+pub async fn upsert_credential_token(db_conn: &mut AsyncPgConnection, token: &CredentialTokenEntity) -> anyhow::Result<()> {
+    use crate::schema::credential_tokens;
+    diesel::insert_into(credential_tokens::table)
+        .values(token)
+        .on_conflict((credential_tokens::domain, credential_tokens::username))
+        .do_update()
+        .set(credential_tokens::jwt.eq(&token.jwt))
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn upsert_credential_role(db_conn: &mut AsyncPgConnection, role: &CredentialRoleEntity) -> anyhow::Result<()> {
+    use crate::schema::credential_roles;
+    diesel::insert_into(credential_roles::table)
+        .values(role)
+        .on_conflict((credential_roles::domain, credential_roles::username))
+        .do_update()
+        .set((credential_roles::is_admin.eq(role.is_admin), credential_roles::communities.eq(&role.communities)))
+        .execute(db_conn)
+        .await?;
     Ok(())
 }
 
-pub async fn get_known_post_ids(db_conn: &mut AsyncPgConnection, ids: Vec<i32>) -> anyhow::Result<Vec<i32>> {
+pub async fn get_known_post_reports(db_conn: &mut AsyncPgConnection, domain_name: &str, ids: Vec<i32>) -> anyhow::Result<Vec<PostReportEntity>> {
     use crate::schema::post_reports::dsl::*;
     let reports: Vec<PostReportEntity> = post_reports
+        .filter(domain.eq(domain_name))
         .filter(id.eq_any(&ids))
         .select(PostReportEntity::as_select())
         .load(db_conn)
         .await?;
 
-    let ids: Vec<i32> = reports.iter().map(|r| r.id).collect::<Vec<_>>();
-
-    Ok(ids)
+    Ok(reports)
 }
 
-pub async fn get_known_comment_ids(db_conn: &mut AsyncPgConnection, ids: Vec<i32>) -> anyhow::Result<Vec<i32>> {
+pub async fn get_known_comment_reports(db_conn: &mut AsyncPgConnection, domain_name: &str, ids: Vec<i32>) -> anyhow::Result<Vec<CommentReportEntity>> {
     use crate::schema::comment_reports::dsl::*;
     let reports: Vec<CommentReportEntity> = comment_reports
+        .filter(domain.eq(domain_name))
         .filter(id.eq_any(&ids))
         .select(CommentReportEntity::as_select())
         .load(db_conn)
         .await?;
 
-    let ids: Vec<i32> = reports.iter().map(|r| r.id).collect::<Vec<_>>();
+    Ok(reports)
+}
+
+pub async fn get_known_private_message_reports(db_conn: &mut AsyncPgConnection, domain_name: &str, ids: Vec<i32>) -> anyhow::Result<Vec<PrivateMessageReportEntity>> {
+    use crate::schema::private_message_reports::dsl::*;
+    let reports: Vec<PrivateMessageReportEntity> = private_message_reports
+        .filter(domain.eq(domain_name))
+        .filter(id.eq_any(&ids))
+        .select(PrivateMessageReportEntity::as_select())
+        .load(db_conn)
+        .await?;
+
+    Ok(reports)
+}
+
+pub async fn get_known_registration_application_ids(db_conn: &mut AsyncPgConnection, domain_name: &str, ids: Vec<i32>) -> anyhow::Result<Vec<i32>> {
+    use crate::schema::registration_applications::dsl::*;
+    let applications: Vec<RegistrationApplicationEntity> = registration_applications
+        .filter(domain.eq(domain_name))
+        .filter(id.eq_any(&ids))
+        .select(RegistrationApplicationEntity::as_select())
+        .load(db_conn)
+        .await?;
+
+    let ids: Vec<i32> = applications.iter().map(|r| r.id).collect::<Vec<_>>();
 
     Ok(ids)
 }
@@ -87,9 +392,10 @@ pub async fn insert_post_reports(db_conn: &mut AsyncPgConnection, domain: &str,
     let new_reports: Vec<PostReportEntity> = reports
         .iter()
         .map(|view| PostReportEntity {
-            id: stupid::extract_post_report_id(view.post_report.id),
+            id: stupid::extract_id(view.post_report.id),
             domain: domain.to_string(),
             data: serde_json::to_value(view).unwrap(),
+            report_key: Some(view.federation_key()),
         })
         .collect();
     diesel::insert_into(post_reports::table)
@@ -108,9 +414,10 @@ pub async fn insert_comment_reports(db_conn: &mut AsyncPgConnection, domain: &st
     let new_comments: Vec<CommentReportEntity> = comments
         .iter()
         .map(|view| CommentReportEntity {
-            id: stupid::extract_comment_report_id(view.comment_report.id),
+            id: stupid::extract_id(view.comment_report.id),
             domain: domain.to_string(),
             data: serde_json::to_value(view).unwrap(),
+            report_key: Some(view.federation_key()),
         })
         .collect();
     diesel::insert_into(comment_reports::table)
@@ -119,4 +426,547 @@ pub async fn insert_comment_reports(db_conn: &mut AsyncPgConnection, domain: &st
         .execute(db_conn)
         .await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub async fn insert_private_message_reports(db_conn: &mut AsyncPgConnection, domain: &str, reports: &[PrivateMessageReportView]) -> anyhow::Result<()> {
+    use crate::schema::private_message_reports;
+    if reports.is_empty() {
+        return Ok(());
+    }
+    let new_reports: Vec<PrivateMessageReportEntity> = reports
+        .iter()
+        .map(|view| PrivateMessageReportEntity {
+            id: stupid::extract_id(view.private_message_report.id),
+            domain: domain.to_string(),
+            data: serde_json::to_value(view).unwrap(),
+            report_key: Some(view.federation_key()),
+        })
+        .collect();
+    diesel::insert_into(private_message_reports::table)
+        .values(&new_reports)
+        .on_conflict_do_nothing()
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_registration_applications(db_conn: &mut AsyncPgConnection, domain: &str, applications: &[RegistrationApplicationView]) -> anyhow::Result<()> {
+    use crate::schema::registration_applications;
+    if applications.is_empty() {
+        return Ok(());
+    }
+    let new_applications: Vec<RegistrationApplicationEntity> = applications
+        .iter()
+        .map(|view| RegistrationApplicationEntity {
+            id: stupid::extract_id(view.registration_application.id),
+            domain: domain.to_string(),
+            data: serde_json::to_value(view).unwrap(),
+        })
+        .collect();
+    diesel::insert_into(registration_applications::table)
+        .values(&new_applications)
+        .on_conflict_do_nothing()
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn update_report_data(db_conn: &mut AsyncPgConnection, kind: ReportKind, report_domain: &str, report_id: i32, report_data: serde_json::Value) -> anyhow::Result<()> {
+    match kind {
+        ReportKind::Post => {
+            use crate::schema::post_reports::dsl::*;
+            diesel::update(post_reports.filter(domain.eq(report_domain)).filter(id.eq(report_id)))
+                .set(data.eq(report_data))
+                .execute(db_conn)
+                .await?;
+        }
+        ReportKind::Comment => {
+            use crate::schema::comment_reports::dsl::*;
+            diesel::update(comment_reports.filter(domain.eq(report_domain)).filter(id.eq(report_id)))
+                .set(data.eq(report_data))
+                .execute(db_conn)
+                .await?;
+        }
+        ReportKind::PrivateMessage => {
+            use crate::schema::private_message_reports::dsl::*;
+            diesel::update(private_message_reports.filter(domain.eq(report_domain)).filter(id.eq(report_id)))
+                .set(data.eq(report_data))
+                .execute(db_conn)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// IDs of the stored reports of the instance that weren't resolved yet and are still listed,
+/// limited to those of the communities when given, as other reports can't be listed by the account
+pub async fn get_open_report_ids(db_conn: &mut AsyncPgConnection, kind: ReportKind, report_domain: &str, communities: Option<&[String]>) -> anyhow::Result<Vec<i32>> {
+    let in_communities = communities.map(|communities| sql::<Text>("data -> 'community' ->> 'actor_id'").eq_any(communities.to_vec()));
+    let ids: Vec<i32> = match kind {
+        ReportKind::Post => {
+            use crate::schema::post_reports::dsl::*;
+            let mut query = post_reports
+                .filter(domain.eq(report_domain))
+                .filter(gone.eq(false))
+                .filter(sql::<Bool>("NOT (data -> 'post_report' ->> 'resolved')::BOOLEAN"))
+                .select(id)
+                .into_boxed();
+            if let Some(in_communities) = in_communities {
+                query = query.filter(in_communities);
+            }
+            query.load(db_conn).await?
+        }
+        ReportKind::Comment => {
+            use crate::schema::comment_reports::dsl::*;
+            let mut query = comment_reports
+                .filter(domain.eq(report_domain))
+                .filter(gone.eq(false))
+                .filter(sql::<Bool>("NOT (data -> 'comment_report' ->> 'resolved')::BOOLEAN"))
+                .select(id)
+                .into_boxed();
+            if let Some(in_communities) = in_communities {
+                query = query.filter(in_communities);
+            }
+            query.load(db_conn).await?
+        }
+        // Private message reports have no community, only admins list them
+        ReportKind::PrivateMessage => {
+            use crate::schema::private_message_reports::dsl::*;
+            private_message_reports
+                .filter(domain.eq(report_domain))
+                .filter(gone.eq(false))
+                .filter(sql::<Bool>("NOT (data -> 'private_message_report' ->> 'resolved')::BOOLEAN"))
+                .select(id)
+                .load(db_conn)
+                .await?
+        }
+    };
+
+    Ok(ids)
+}
+
+/// Gone reports aren't listed by the instance anymore, e.g. because they were deleted or are beyond the page limit.
+/// They are left out of the open reports until they are listed again.
+pub async fn set_reports_gone(db_conn: &mut AsyncPgConnection, kind: ReportKind, report_domain: &str, ids: &[i32], is_gone: bool) -> anyhow::Result<()> {
+    match kind {
+        ReportKind::Post => {
+            use crate::schema::post_reports::dsl::*;
+            diesel::update(post_reports.filter(domain.eq(report_domain)).filter(id.eq_any(ids)).filter(gone.ne(is_gone)))
+                .set(gone.eq(is_gone))
+                .execute(db_conn)
+                .await?;
+        }
+        ReportKind::Comment => {
+            use crate::schema::comment_reports::dsl::*;
+            diesel::update(comment_reports.filter(domain.eq(report_domain)).filter(id.eq_any(ids)).filter(gone.ne(is_gone)))
+                .set(gone.eq(is_gone))
+                .execute(db_conn)
+                .await?;
+        }
+        ReportKind::PrivateMessage => {
+            use crate::schema::private_message_reports::dsl::*;
+            diesel::update(private_message_reports.filter(domain.eq(report_domain)).filter(id.eq_any(ids)).filter(gone.ne(is_gone)))
+                .set(gone.eq(is_gone))
+                .execute(db_conn)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the domain and ID of every stored copy of a report, the first one being the copy which was seen first
+pub async fn get_report_copies(db_conn: &mut AsyncPgConnection, kind: ReportKind, key: &str) -> anyhow::Result<Vec<(String, i32)>> {
+    let copies: Vec<(String, i32)> = match kind {
+        ReportKind::Post => {
+            use crate::schema::post_reports::dsl::*;
+            post_reports
+                .filter(report_key.eq(key))
+                .order((first_seen.asc(), domain.asc(), id.asc()))
+                .select((domain, id))
+                .load(db_conn)
+                .await?
+        }
+        ReportKind::Comment => {
+            use crate::schema::comment_reports::dsl::*;
+            comment_reports
+                .filter(report_key.eq(key))
+                .order((first_seen.asc(), domain.asc(), id.asc()))
+                .select((domain, id))
+                .load(db_conn)
+                .await?
+        }
+        ReportKind::PrivateMessage => {
+            use crate::schema::private_message_reports::dsl::*;
+            private_message_reports
+                .filter(report_key.eq(key))
+                .order((first_seen.asc(), domain.asc(), id.asc()))
+                .select((domain, id))
+                .load(db_conn)
+                .await?
+        }
+    };
+
+    Ok(copies)
+}
+
+/// Returns the ID of the stored change
+pub async fn insert_report_history(db_conn: &mut AsyncPgConnection, domain: &str, update: &ReportUpdate) -> anyhow::Result<i64> {
+    use crate::schema::report_history;
+    let entry = NewReportHistoryEntity {
+        domain: domain.to_string(),
+        kind: update.kind.to_string(),
+        report_id: update.report_id,
+        resolved: update.resolved,
+        resolver_id: update.resolver_id,
+        content_removed: update.content_removed,
+    };
+    let history_id = diesel::insert_into(report_history::table)
+        .values(&entry)
+        .returning(report_history::id)
+        .get_result(db_conn)
+        .await?;
+    Ok(history_id)
+}
+
+pub async fn insert_discord_message(db_conn: &mut AsyncPgConnection, message: &DiscordMessageEntity) -> anyhow::Result<()> {
+    use crate::schema::discord_messages;
+    diesel::insert_into(discord_messages::table)
+        .values(message)
+        .on_conflict_do_nothing()
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_discord_message_id(db_conn: &mut AsyncPgConnection, report_domain: &str, report_kind: ReportKind, id: i32, webhook: &str) -> anyhow::Result<Option<String>> {
+    use crate::schema::discord_messages::dsl::*;
+    let message: Option<DiscordMessageEntity> = discord_messages
+        .filter(domain.eq(report_domain))
+        .filter(kind.eq(report_kind.as_str()))
+        .filter(report_id.eq(id))
+        .filter(webhook_id.eq(webhook))
+        .select(DiscordMessageEntity::as_select())
+        .first(db_conn)
+        .await
+        .optional()?;
+
+    Ok(message.map(|m| m.message_id))
+}
+
+/// Compares reports first seen within the window with the instances that are expected to receive them,
+/// because one of their accounts moderates the community or is an admin of the instance the community is on.
+/// Reports that are younger than the grace period are left out because they might still federate.
+pub async fn get_federation_gaps(db_conn: &mut AsyncPgConnection, window_seconds: u64, grace_seconds: u64) -> anyhow::Result<Vec<FederationGap>> {
+    use diesel::sql_types::Double;
+    let gaps: Vec<FederationGap> = sql_query(r#"
+        WITH reports AS (
+            SELECT domain, report_key, first_seen, data -> 'community' ->> 'actor_id' AS community
+            FROM post_reports
+            WHERE report_key IS NOT NULL
+            UNION ALL
+            SELECT domain, report_key, first_seen, data -> 'community' ->> 'actor_id' AS community
+            FROM comment_reports
+            WHERE report_key IS NOT NULL
+        ),
+        coverage AS (
+            SELECT DISTINCT role.domain, community.community
+            FROM credential_roles role
+            JOIN credentials credential ON credential.domain = role.domain AND credential.username = role.username
+            JOIN (SELECT DISTINCT community FROM reports) community
+                ON community.community = ANY (role.communities)
+                OR (role.is_admin AND split_part(community.community, '/', 3) = role.domain)
+        ),
+        sightings AS (
+            SELECT report_key, community, domain, MIN(first_seen) AS first_seen
+            FROM reports
+            GROUP BY report_key, community, domain
+        )
+        SELECT source.domain AS source_domain,
+               target.domain AS target_domain,
+               COUNT(*) FILTER (WHERE copy.report_key IS NULL) AS missing,
+               COUNT(copy.report_key) AS federated,
+               AVG(EXTRACT(EPOCH FROM copy.first_seen - source.first_seen))::DOUBLE PRECISION AS average_lag_seconds,
+               MAX(EXTRACT(EPOCH FROM copy.first_seen - source.first_seen))::DOUBLE PRECISION AS max_lag_seconds
+        FROM sightings source
+        JOIN coverage target ON target.community = source.community AND target.domain <> source.domain
+        LEFT JOIN sightings copy ON copy.report_key = source.report_key AND copy.domain = target.domain
+        WHERE source.first_seen < now() - make_interval(secs => $2)
+          AND source.first_seen >= now() - make_interval(secs => $1 + $2)
+          -- Copies that were seen earlier on the target are counted for the opposite direction
+          AND (copy.report_key IS NULL OR copy.first_seen >= source.first_seen)
+        GROUP BY source.domain, target.domain
+        ORDER BY missing DESC, source.domain, target.domain;
+    "#)
+        .bind::<Double, _>(window_seconds as f64)
+        .bind::<Double, _>(grace_seconds as f64)
+        .load(db_conn)
+        .await?;
+
+    Ok(gaps)
+}
+
+/// Includes every community that ever had a report, so counts that dropped to zero are reported as well
+pub async fn get_open_report_counts(db_conn: &mut AsyncPgConnection) -> anyhow::Result<Vec<OpenReportCount>> {
+    let counts: Vec<OpenReportCount> = sql_query(r#"
+        WITH reports AS (
+            SELECT domain, data -> 'community' AS community, 'post' AS kind,
+                   NOT gone AND NOT (data -> 'post_report' ->> 'resolved')::BOOLEAN AS open
+            FROM post_reports
+            UNION ALL
+            SELECT domain, data -> 'community' AS community, 'comment' AS kind,
+                   NOT gone AND NOT (data -> 'comment_report' ->> 'resolved')::BOOLEAN AS open
+            FROM comment_reports
+        ), counts AS (
+            SELECT domain,
+                   community ->> 'name' AS community_name,
+                   community ->> 'actor_id' AS community_actor_id,
+                   COUNT(*) FILTER (WHERE open AND kind = 'post') AS posts,
+                   COUNT(*) FILTER (WHERE open AND kind = 'comment') AS comments
+            FROM reports
+            WHERE community IS NOT NULL
+            GROUP BY domain, community ->> 'name', community ->> 'actor_id'
+        )
+        SELECT domain, community_name, community_actor_id, posts, comments
+        FROM counts
+        UNION ALL
+        -- Polled instances without community reports, so their count is 0 instead of missing
+        SELECT DISTINCT c.domain, NULL::TEXT, NULL::TEXT, 0::BIGINT, 0::BIGINT
+        FROM credentials c
+        WHERE NOT EXISTS (SELECT 1 FROM counts WHERE counts.domain = c.domain)
+        ORDER BY domain, community_actor_id;
+    "#)
+        .load(db_conn)
+        .await?;
+
+    Ok(counts)
+}
+
+/// Stores the result of polling an instance with an account, `error` is `None` when it succeeded
+pub async fn record_instance_poll(db_conn: &mut AsyncPgConnection, domain: &str, username: &str, logged_in: bool, error: Option<&str>) -> anyhow::Result<()> {
+    use diesel::sql_types::{Bool, Nullable, Text};
+    sql_query(r#"
+        INSERT INTO instance_polls (domain, username, last_poll, last_success, logged_in, last_error)
+        VALUES ($1, $2, now(), CASE WHEN $4 IS NULL THEN now() END, $3, $4)
+        ON CONFLICT (domain, username) DO UPDATE
+        SET last_poll    = excluded.last_poll,
+            last_success = COALESCE(excluded.last_success, instance_polls.last_success),
+            logged_in    = excluded.logged_in,
+            last_error   = excluded.last_error;
+    "#)
+        .bind::<Text, _>(domain)
+        .bind::<Text, _>(username)
+        .bind::<Bool, _>(logged_in)
+        .bind::<Nullable<Text>, _>(error)
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+/// One status per instance with credentials, also those that weren't polled yet
+pub async fn get_instance_statuses(db_conn: &mut AsyncPgConnection) -> anyhow::Result<Vec<InstanceStatus>> {
+    let statuses: Vec<InstanceStatus> = sql_query(r#"
+        SELECT c.domain,
+               to_char(MAX(p.last_poll) AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS last_poll,
+               to_char(MAX(p.last_success) AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS last_success,
+               BOOL_AND(p.logged_in) AS logged_in,
+               MAX(p.last_error) AS last_error
+        FROM credentials c
+        LEFT JOIN instance_polls p ON p.domain = c.domain AND p.username = c.username
+        GROUP BY c.domain
+        ORDER BY c.domain;
+    "#)
+        .load(db_conn)
+        .await?;
+
+    Ok(statuses)
+}
+
+pub const OUTBOX_PENDING: &str = "pending";
+pub const OUTBOX_DEAD: &str = "dead";
+
+pub async fn insert_outbox_notifications(db_conn: &mut AsyncPgConnection, notifications: &[NewOutboxNotificationEntity]) -> anyhow::Result<()> {
+    use crate::schema::notification_outbox;
+    if notifications.is_empty() {
+        return Ok(());
+    }
+    diesel::insert_into(notification_outbox::table)
+        .values(notifications)
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+/// Pending notifications of the notifier of which the next attempt is due, oldest first
+/// Notifications of a report wait until the earlier ones of the same report were delivered,
+/// so e.g. an update is never sent before the report it updates
+pub async fn get_due_outbox_notifications(db_conn: &mut AsyncPgConnection, notifier_name: &str, limit: i64) -> anyhow::Result<Vec<OutboxNotificationEntity>> {
+    use crate::schema::notification_outbox::dsl::*;
+    let earlier_pending = format!(r#"
+        NOT EXISTS (
+            SELECT 1 FROM notification_outbox earlier
+            WHERE earlier.notifier = notification_outbox.notifier
+              AND earlier.report = notification_outbox.report
+              AND earlier.status = '{OUTBOX_PENDING}'
+              AND earlier.id < notification_outbox.id
+        )
+    "#);
+    let notifications = notification_outbox
+        .filter(notifier.eq(notifier_name))
+        .filter(status.eq(OUTBOX_PENDING))
+        .filter(next_attempt_at.le(diesel::dsl::now))
+        .filter(sql::<Bool>(&earlier_pending))
+        .order(id.asc())
+        .limit(limit)
+        .select(OutboxNotificationEntity::as_select())
+        .load(db_conn)
+        .await?;
+    Ok(notifications)
+}
+
+pub async fn get_outbox_notifications(db_conn: &mut AsyncPgConnection, notification_status: Option<&str>) -> anyhow::Result<Vec<OutboxNotificationEntity>> {
+    use crate::schema::notification_outbox::dsl::*;
+    let mut query = notification_outbox
+        .select(OutboxNotificationEntity::as_select())
+        .order(id.asc())
+        .into_boxed();
+    if let Some(notification_status) = notification_status {
+        query = query.filter(status.eq(notification_status.to_string()));
+    }
+    Ok(query.load(db_conn).await?)
+}
+
+/// Delivered notifications aren't kept
+pub async fn delete_outbox_notification(db_conn: &mut AsyncPgConnection, notification_id: i64) -> anyhow::Result<()> {
+    use crate::schema::notification_outbox::dsl::*;
+    diesel::delete(notification_outbox.filter(id.eq(notification_id)))
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+/// Schedules the next attempt, or moves the notification to the dead letters when `retry_in_seconds` is `None`
+pub async fn fail_outbox_notification(db_conn: &mut AsyncPgConnection, notification_id: i64, error: &str, retry_in_seconds: Option<u64>) -> anyhow::Result<()> {
+    use diesel::sql_types::{BigInt, Double, Text};
+    let new_status = match retry_in_seconds {
+        Some(_) => OUTBOX_PENDING,
+        None => OUTBOX_DEAD,
+    };
+    sql_query(r#"
+        UPDATE notification_outbox
+        SET attempts = attempts + 1,
+            last_error = $1,
+            status = $2,
+            next_attempt_at = now() + make_interval(secs => $3)
+        WHERE id = $4;
+    "#)
+        .bind::<Text, _>(error)
+        .bind::<Text, _>(new_status)
+        .bind::<Double, _>(retry_in_seconds.unwrap_or_default() as f64)
+        .bind::<BigInt, _>(notification_id)
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+/// Pending notifications of notifiers that were removed from the configuration would never be delivered
+pub async fn kill_unknown_outbox_notifications(db_conn: &mut AsyncPgConnection, notifier_names: &[String]) -> anyhow::Result<()> {
+    use crate::schema::notification_outbox::dsl::*;
+    diesel::update(notification_outbox)
+        .filter(status.eq(OUTBOX_PENDING))
+        .filter(notifier.ne_all(notifier_names.to_vec()))
+        .set((status.eq(OUTBOX_DEAD), last_error.eq("Notifier isn't configured")))
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+/// Moves dead notifications back to pending, all of them when no IDs are given
+pub async fn retry_dead_outbox_notifications(db_conn: &mut AsyncPgConnection, notification_ids: &[i64]) -> anyhow::Result<usize> {
+    use crate::schema::notification_outbox::dsl::*;
+    let mut query = diesel::update(notification_outbox)
+        .filter(status.eq(OUTBOX_DEAD))
+        .into_boxed();
+    if !notification_ids.is_empty() {
+        query = query.filter(id.eq_any(notification_ids.to_vec()));
+    }
+    let count = query
+        .set((
+            status.eq(OUTBOX_PENDING),
+            attempts.eq(0),
+            next_attempt_at.eq(diesel::dsl::now),
+        ))
+        .execute(db_conn)
+        .await?;
+    Ok(count)
+}
+
+pub async fn insert_matrix_message(db_conn: &mut AsyncPgConnection, message: &MatrixMessageEntity) -> anyhow::Result<()> {
+    use crate::schema::matrix_messages;
+    diesel::insert_into(matrix_messages::table)
+        .values(message)
+        .on_conflict_do_nothing()
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+/// Event that started the thread of a report, `None` when it wasn't sent to the room
+pub async fn get_matrix_report_thread(db_conn: &mut AsyncPgConnection, report_domain: &str, report_kind: ReportKind, id: i32, room: &str) -> anyhow::Result<Option<String>> {
+    use crate::schema::matrix_messages::dsl::*;
+    let message: Option<MatrixMessageEntity> = matrix_messages
+        .filter(domain.eq(report_domain))
+        .filter(kind.eq(report_kind.as_str()))
+        .filter(report_id.eq(id))
+        .filter(room_id.eq(room))
+        .select(MatrixMessageEntity::as_select())
+        .first(db_conn)
+        .await
+        .optional()?;
+
+    Ok(message.map(|m| m.thread_root.unwrap_or(m.event_id)))
+}
+
+/// Event that started the thread of the first report of a post, `None` when no report of the post was sent to the room
+pub async fn get_matrix_post_thread(db_conn: &mut AsyncPgConnection, report_domain: &str, post: i32, room: &str) -> anyhow::Result<Option<String>> {
+    use crate::schema::matrix_messages::dsl::*;
+    let message: Option<MatrixMessageEntity> = matrix_messages
+        .filter(domain.eq(report_domain))
+        .filter(post_id.eq(post))
+        .filter(room_id.eq(room))
+        .filter(thread_root.is_null())
+        .select(MatrixMessageEntity::as_select())
+        .first(db_conn)
+        .await
+        .optional()?;
+
+    Ok(message.map(|m| m.event_id))
+}
+
+pub async fn insert_email_digest_entry(db_conn: &mut AsyncPgConnection, entry: &NewEmailDigestEntryEntity) -> anyhow::Result<()> {
+    use crate::schema::email_digest_entries;
+    diesel::insert_into(email_digest_entries::table)
+        .values(entry)
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
+
+/// Entries waiting for the next digest of the notifier, oldest first
+pub async fn get_email_digest_entries(db_conn: &mut AsyncPgConnection, notifier_name: &str) -> anyhow::Result<Vec<EmailDigestEntryEntity>> {
+    use crate::schema::email_digest_entries::dsl::*;
+    let entries = email_digest_entries
+        .filter(notifier.eq(notifier_name))
+        .order(id.asc())
+        .select(EmailDigestEntryEntity::as_select())
+        .load(db_conn)
+        .await?;
+    Ok(entries)
+}
+
+pub async fn delete_email_digest_entries(db_conn: &mut AsyncPgConnection, ids: Vec<i64>) -> anyhow::Result<()> {
+    use crate::schema::email_digest_entries::dsl::*;
+    diesel::delete(email_digest_entries.filter(id.eq_any(ids)))
+        .execute(db_conn)
+        .await?;
+    Ok(())
+}
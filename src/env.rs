@@ -1,25 +1,44 @@
-use clap::Parser;
+use crate::config::{Config, DiscordConfig, EmailConfig, MatrixConfig, MqttConfig, NtfyConfig, SmtpSecurity, TelegramConfig, WebhookConfig, DEFAULT_MQTT_AVAILABILITY_TOPIC, DEFAULT_MQTT_OPEN_REPORTS_TOPIC, DEFAULT_MQTT_STATUS_TOPIC, DEFAULT_MQTT_TOPIC};
+use crate::routing::RouteConfig;
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use lettre::message::Mailbox;
+use reqwest::Url;
+use rumqttc::QoS;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
 use std::str::FromStr;
 
+pub const CONSOLE_NAME: &str = "console";
+/// Names of the notifiers configured through CLI options and environment variables
+pub const DEFAULT_DISCORD_NAME: &str = "discord";
+pub const DEFAULT_NTFY_NAME: &str = "ntfy";
+pub const DEFAULT_MQTT_NAME: &str = "mqtt";
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct EnvArgs {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// TOML file with options and notifiers, CLI options and environment variables take precedence
+    #[arg(short, long, env)]
+    pub config: Option<PathBuf>,
     /// Host of Postgres database
     #[arg(long, env)]
-    pub db_host: String,
+    pub db_host: Option<String>,
     /// Port of Postgres database
     #[arg(long, env)]
-    pub db_port: u16,
+    pub db_port: Option<u16>,
     /// Username for Postgres database
     #[arg(long, env)]
-    pub db_user: String,
+    pub db_user: Option<String>,
     /// Password for Postgres database
     #[arg(long, env)]
-    pub db_password: String,
+    pub db_password: Option<String>,
     /// Database name of Postgres database
     #[arg(long, env)]
-    pub db_name: String,
+    pub db_name: Option<String>,
     /// Optional Discord webhook
     #[arg(short, long, env)]
     pub discord_webhook: Option<DiscordWebhook>,
@@ -47,9 +66,68 @@ pub struct EnvArgs {
     /// Password for optional MQTT broker
     #[arg(long, env)]
     pub mqtt_password: Option<String>,
-    /// Interval in seconds to send request to check for reports
-    #[arg(short, long, env, default_value_t = 60)]
-    pub interval: u64,
+    /// Interval in seconds to send request to check for reports [default: 60]
+    #[arg(short, long, env)]
+    pub interval: Option<u64>,
+    /// Timeout in seconds for checking the reports of an instance [default: 30]
+    #[arg(long, env)]
+    pub timeout: Option<u64>,
+    /// Maximum time in seconds to wait before checking an instance again after failures [default: 3600]
+    #[arg(long, env)]
+    pub max_backoff: Option<u64>,
+    /// Maximum number of report pages requested per instance each check [default: 10]
+    #[arg(long, env)]
+    pub max_report_pages: Option<i64>,
+    /// Include private message content in notifications instead of redacting it
+    #[arg(long, env, num_args = 0..=1, default_missing_value = "true")]
+    pub show_private_message_content: Option<bool>,
+    /// Interval in seconds to send a summary of reports that didn't federate, disabled when 0 [default: 0]
+    #[arg(long, env)]
+    pub federation_gap_interval: Option<u64>,
+    /// Seconds a report is given to federate before it's counted as missing [default: 3600]
+    #[arg(long, env)]
+    pub federation_gap_grace: Option<u64>,
+    /// Key used to encrypt the JWTs stored in the database, tokens aren't stored without it
+    #[arg(long, env)]
+    pub jwt_encryption_key: Option<String>,
+    /// Number of attempts to send a notification before it's moved to the dead letters [default: 10]
+    #[arg(long, env)]
+    pub outbox_max_attempts: Option<i32>,
+    /// Timeout in seconds for sending a single notification [default: 10]
+    #[arg(long, env)]
+    pub notify_timeout: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Inspect and retry notifications that weren't delivered yet
+    Outbox {
+        #[command(subcommand)]
+        command: OutboxCommand,
+    },
+    /// Show which notifiers would receive a report
+    Route {
+        /// JSON file with an outbox payload or the data stored for a report
+        #[arg(long = "dry-run", value_name = "REPORT_JSON")]
+        report: PathBuf,
+        /// Instance the report was seen on, required for stored report data
+        #[arg(long)]
+        domain: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum OutboxCommand {
+    /// List pending and dead notifications
+    List {
+        /// Only list notifications that won't be retried anymore
+        #[arg(long)]
+        dead: bool,
+    },
+    /// Retry dead notifications, all of them when no IDs are given
+    Retry {
+        ids: Vec<i64>,
+    },
 }
 
 pub struct NtfyCredentialEnvVariables {
@@ -58,28 +136,49 @@ pub struct NtfyCredentialEnvVariables {
 }
 
 pub struct NtfyEnvVariables {
+    pub name: String,
     pub host: String,
     pub topic: String,
     pub credentials: Option<NtfyCredentialEnvVariables>,
+    pub templates: HashMap<String, String>,
+}
+
+fn ntfy_credentials(username: &Option<String>, password: &Option<String>) -> Result<Option<NtfyCredentialEnvVariables>, &'static str> {
+    match (username, password) {
+        (Some(username), Some(password)) => Ok(Some(NtfyCredentialEnvVariables {
+            username: username.clone(),
+            password: password.clone(),
+        })),
+        (Some(_), None) => Err("Ntfy username provided but no password specified"),
+        (None, Some(_)) => Err("Ntfy password provided but no username specified"),
+        (None, None) => Ok(None),
+    }
 }
 
 impl TryFrom<&EnvArgs> for NtfyEnvVariables {
     type Error = &'static str;
 
     fn try_from(value: &EnvArgs) -> Result<Self, Self::Error> {
-        let credentials = match (&value.ntfy_username, &value.ntfy_password) {
-            (Some(username), Some(password)) => Some(NtfyCredentialEnvVariables {
-                username: username.clone(),
-                password: password.clone(),
-            }),
-            (Some(_), None) => Err("Ntfy username provided but no password specified")?,
-            (None, Some(_)) => Err("Ntfy password provided but no username specified")?,
-            (None, None) => None,
-        };
         Ok(NtfyEnvVariables {
+            name: DEFAULT_NTFY_NAME.to_string(),
             host: value.ntfy_host.clone().ok_or("No ntfy host set")?,
             topic: value.ntfy_topic.clone(),
-            credentials,
+            credentials: ntfy_credentials(&value.ntfy_username, &value.ntfy_password)?,
+            templates: HashMap::new(),
+        })
+    }
+}
+
+impl TryFrom<&NtfyConfig> for NtfyEnvVariables {
+    type Error = &'static str;
+
+    fn try_from(value: &NtfyConfig) -> Result<Self, Self::Error> {
+        Ok(NtfyEnvVariables {
+            name: value.name.clone(),
+            host: value.host.clone(),
+            topic: value.topic.clone(),
+            credentials: ntfy_credentials(&value.username, &value.password)?,
+            templates: value.templates.clone(),
         })
     }
 }
@@ -89,45 +188,338 @@ pub struct MqttCredentialEnvVariables {
     pub password: String,
 }
 
+pub struct MqttCommandEnvVariables {
+    pub topic: String,
+    pub response_topic: String,
+    /// Key of the HMAC every command is signed with
+    pub secret: String,
+}
+
+pub struct MqttTlsEnvVariables {
+    /// The system's root certificates are used without one
+    pub ca_file: Option<PathBuf>,
+    /// Certificate and key file
+    pub client_auth: Option<(PathBuf, PathBuf)>,
+}
+
 pub struct MqttEnvVariables {
+    pub name: String,
     pub host: String,
     pub port: u16,
     pub credentials: Option<MqttCredentialEnvVariables>,
+    pub topic: String,
+    pub client_id: String,
+    pub qos: QoS,
+    pub retain: bool,
+    pub tls: Option<MqttTlsEnvVariables>,
+    pub availability_topic: String,
+    pub open_reports_topic: String,
+    pub status_topic: String,
+    /// Discovery prefix and topic of the report events, when Home Assistant discovery is enabled
+    pub home_assistant: Option<(String, String)>,
+    /// When commands are enabled
+    pub commands: Option<MqttCommandEnvVariables>,
+}
+
+/// Brokers disconnect clients with the same ID, so additional notifiers get their own
+fn default_mqtt_client_id(name: &str) -> String {
+    match name {
+        DEFAULT_MQTT_NAME => "lemmy-know".to_string(),
+        name => format!("lemmy-know-{name}"),
+    }
+}
+
+fn mqtt_qos(qos: u8) -> Result<QoS, &'static str> {
+    match qos {
+        0 => Ok(QoS::AtMostOnce),
+        1 => Ok(QoS::AtLeastOnce),
+        2 => Ok(QoS::ExactlyOnce),
+        _ => Err("QoS has to be 0, 1 or 2"),
+    }
+}
+
+fn mqtt_tls(value: &MqttConfig) -> Result<Option<MqttTlsEnvVariables>, &'static str> {
+    let client_auth = match (&value.client_cert_file, &value.client_key_file) {
+        (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+        (Some(_), None) => return Err("Client certificate provided but no key specified"),
+        (None, Some(_)) => return Err("Client key provided but no certificate specified"),
+        (None, None) => None,
+    };
+    if !value.tls {
+        return match value.ca_file.is_some() || client_auth.is_some() {
+            true => Err("Certificate files require tls = true"),
+            false => Ok(None),
+        };
+    }
+    if client_auth.is_some() && value.ca_file.is_none() {
+        return Err("Client certificates require a CA file");
+    }
+    Ok(Some(MqttTlsEnvVariables { ca_file: value.ca_file.clone(), client_auth }))
+}
+
+fn mqtt_credentials(user: &Option<String>, password: &Option<String>) -> Result<Option<MqttCredentialEnvVariables>, &'static str> {
+    match (user, password) {
+        (Some(user), Some(password)) => Ok(Some(MqttCredentialEnvVariables {
+            user: user.clone(),
+            password: password.clone(),
+        })),
+        (Some(_), None) => Err("MQTT username provided but no password specified"),
+        (None, Some(_)) => Err("MQTT password provided but no username specified"),
+        (None, None) => Ok(None),
+    }
 }
 
 impl TryFrom<&EnvArgs> for MqttEnvVariables {
     type Error = &'static str;
 
     fn try_from(value: &EnvArgs) -> Result<Self, Self::Error> {
-        let credentials = match (&value.mqtt_user, &value.mqtt_password) {
-            (Some(user), Some(password)) => Some(MqttCredentialEnvVariables {
-                user: user.clone(),
-                password: password.clone(),
+        Ok(MqttEnvVariables {
+            name: DEFAULT_MQTT_NAME.to_string(),
+            host: value.mqtt_host.clone().ok_or("No MQTT host set")?,
+            port: value.mqtt_port.ok_or("No MQTT port set")?,
+            credentials: mqtt_credentials(&value.mqtt_user, &value.mqtt_password)?,
+            topic: DEFAULT_MQTT_TOPIC.to_string(),
+            client_id: default_mqtt_client_id(DEFAULT_MQTT_NAME),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            tls: None,
+            availability_topic: DEFAULT_MQTT_AVAILABILITY_TOPIC.to_string(),
+            open_reports_topic: DEFAULT_MQTT_OPEN_REPORTS_TOPIC.to_string(),
+            status_topic: DEFAULT_MQTT_STATUS_TOPIC.to_string(),
+            home_assistant: None,
+            commands: None,
+        })
+    }
+}
+
+impl TryFrom<&MqttConfig> for MqttEnvVariables {
+    type Error = &'static str;
+
+    fn try_from(value: &MqttConfig) -> Result<Self, Self::Error> {
+        // Wildcards are only allowed when subscribing
+        let mut topics = [&value.topic, &value.availability_topic, &value.open_reports_topic, &value.status_topic, &value.discovery_prefix, &value.report_events_topic]
+            .into_iter()
+            .chain(&value.command_topic)
+            .chain(&value.response_topic);
+        if topics.any(|topic| topic.is_empty() || topic.contains(['+', '#'])) {
+            return Err("Topics can't be empty or contain wildcards");
+        }
+        if value.response_topic.is_some() && value.command_topic.is_none() {
+            return Err("Response topic provided but no command topic specified");
+        }
+        // Anyone who can publish to the topic could otherwise moderate with the accounts
+        let commands = match (&value.command_topic, &value.command_secret) {
+            (Some(command_topic), Some(secret)) if !secret.is_empty() => Some(MqttCommandEnvVariables {
+                topic: command_topic.clone(),
+                response_topic: value.response_topic.clone().unwrap_or_else(|| format!("{}/response", command_topic.trim_end_matches('/'))),
+                secret: secret.clone(),
             }),
-            (Some(_), None) => Err("MQTT username provided but no password specified")?,
-            (None, Some(_)) => Err("MQTT password provided but no username specified")?,
+            (Some(_), _) => return Err("Command topic provided but no command secret specified"),
+            (None, Some(_)) => return Err("Command secret provided but no command topic specified"),
             (None, None) => None,
         };
         Ok(MqttEnvVariables {
-            host: value.mqtt_host.clone().ok_or("No MQTT host set")?,
-            port: value.mqtt_port.ok_or("No MQTT port set")?,
-            credentials,
+            name: value.name.clone(),
+            host: value.host.clone(),
+            port: value.port,
+            credentials: mqtt_credentials(&value.user, &value.password)?,
+            topic: value.topic.clone(),
+            client_id: value.client_id.clone().unwrap_or_else(|| default_mqtt_client_id(&value.name)),
+            qos: mqtt_qos(value.qos)?,
+            retain: value.retain,
+            tls: mqtt_tls(value)?,
+            availability_topic: value.availability_topic.clone(),
+            open_reports_topic: value.open_reports_topic.trim_end_matches('/').to_string(),
+            status_topic: value.status_topic.trim_end_matches('/').to_string(),
+            home_assistant: value.home_assistant.then(|| (
+                value.discovery_prefix.trim_end_matches('/').to_string(),
+                value.report_events_topic.trim_end_matches('/').to_string(),
+            )),
+            commands,
+        })
+    }
+}
+
+pub struct DiscordEnvVariables {
+    pub name: String,
+    pub webhook: DiscordWebhook,
+    pub templates: HashMap<String, String>,
+}
+
+impl TryFrom<&DiscordConfig> for DiscordEnvVariables {
+    type Error = &'static str;
+
+    fn try_from(value: &DiscordConfig) -> Result<Self, Self::Error> {
+        Ok(DiscordEnvVariables {
+            name: value.name.clone(),
+            webhook: value.webhook.parse()?,
+            templates: value.templates.clone(),
+        })
+    }
+}
+
+pub struct MatrixEnvVariables {
+    pub name: String,
+    pub homeserver: Url,
+    pub access_token: String,
+    pub room_id: String,
+    pub threads: bool,
+    pub templates: HashMap<String, String>,
+}
+
+impl TryFrom<&MatrixConfig> for MatrixEnvVariables {
+    type Error = &'static str;
+
+    fn try_from(value: &MatrixConfig) -> Result<Self, Self::Error> {
+        if !value.room_id.starts_with('!') {
+            return Err("Matrix room ID must start with '!', aliases aren't supported");
+        }
+        Ok(MatrixEnvVariables {
+            name: value.name.clone(),
+            homeserver: Url::parse(&value.homeserver).map_err(|_| "Invalid Matrix homeserver URL")?,
+            access_token: value.access_token.clone(),
+            room_id: value.room_id.clone(),
+            threads: value.threads,
+            templates: value.templates.clone(),
+        })
+    }
+}
+
+pub struct TelegramEnvVariables {
+    pub name: String,
+    pub bot_token: String,
+    pub chat_id: String,
+    pub api_url: Url,
+    pub buttons: bool,
+    pub allowed_users: Vec<i64>,
+    pub templates: HashMap<String, String>,
+}
+
+impl TryFrom<&TelegramConfig> for TelegramEnvVariables {
+    type Error = &'static str;
+
+    fn try_from(value: &TelegramConfig) -> Result<Self, Self::Error> {
+        // Anyone who can see the chat could otherwise act on reports with the moderator accounts
+        if value.buttons && value.allowed_users.is_empty() {
+            return Err("Telegram buttons require allowed_users, set buttons = false to send reports without them");
+        }
+        Ok(TelegramEnvVariables {
+            name: value.name.clone(),
+            bot_token: value.bot_token.clone(),
+            chat_id: value.chat_id.clone(),
+            api_url: Url::parse(&value.api_url).map_err(|_| "Invalid Telegram API URL")?,
+            buttons: value.buttons,
+            allowed_users: value.allowed_users.clone(),
+            templates: value.templates.clone(),
+        })
+    }
+}
+
+pub struct WebhookEnvVariables {
+    pub name: String,
+    pub url: Url,
+    pub headers: HashMap<String, String>,
+    pub secret: Option<String>,
+    pub retries: u32,
+    pub content_type: String,
+    pub templates: HashMap<String, String>,
+}
+
+impl TryFrom<&WebhookConfig> for WebhookEnvVariables {
+    type Error = &'static str;
+
+    fn try_from(value: &WebhookConfig) -> Result<Self, Self::Error> {
+        Ok(WebhookEnvVariables {
+            name: value.name.clone(),
+            url: Url::parse(&value.url).map_err(|_| "Invalid webhook URL")?,
+            headers: value.headers.clone(),
+            secret: value.secret.clone(),
+            retries: value.retries,
+            content_type: value.content_type.clone(),
+            templates: value.templates.clone(),
+        })
+    }
+}
+
+pub struct EmailCredentialEnvVariables {
+    pub username: String,
+    pub password: String,
+}
+
+pub struct EmailEnvVariables {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub credentials: Option<EmailCredentialEnvVariables>,
+    pub from: Mailbox,
+    pub to: Vec<Mailbox>,
+    pub digest_interval: Option<u64>,
+    pub templates: HashMap<String, String>,
+}
+
+fn email_credentials(username: &Option<String>, password: &Option<String>) -> Result<Option<EmailCredentialEnvVariables>, &'static str> {
+    match (username, password) {
+        (Some(username), Some(password)) => Ok(Some(EmailCredentialEnvVariables {
+            username: username.clone(),
+            password: password.clone(),
+        })),
+        (Some(_), None) => Err("SMTP username provided but no password specified"),
+        (None, Some(_)) => Err("SMTP password provided but no username specified"),
+        (None, None) => Ok(None),
+    }
+}
+
+impl TryFrom<&EmailConfig> for EmailEnvVariables {
+    type Error = &'static str;
+
+    fn try_from(value: &EmailConfig) -> Result<Self, Self::Error> {
+        if value.to.is_empty() {
+            return Err("No recipients set");
+        }
+        Ok(EmailEnvVariables {
+            name: value.name.clone(),
+            host: value.host.clone(),
+            port: value.port.unwrap_or(value.security.default_port()),
+            security: value.security,
+            credentials: email_credentials(&value.username, &value.password)?,
+            from: value.from.parse().map_err(|_| "Invalid from address")?,
+            to: value.to.iter().map(|to| to.parse()).collect::<Result<_, _>>().map_err(|_| "Invalid to address")?,
+            digest_interval: value.digest_interval.map(|interval| interval.max(1)),
+            templates: value.templates.clone(),
         })
     }
 }
 
 pub struct EnvVariables {
+    pub command: Option<Command>,
     pub db_host: String,
     pub db_port: u16,
     pub db_user: String,
     pub db_password: String,
     pub db_name: String,
-    pub discord_webhook: Option<DiscordWebhook>,
-    pub ntfy: Option<NtfyEnvVariables>,
-    pub mqtt: Option<MqttEnvVariables>,
+    pub discord: Vec<DiscordEnvVariables>,
+    pub ntfy: Vec<NtfyEnvVariables>,
+    pub mqtt: Vec<MqttEnvVariables>,
+    pub matrix: Vec<MatrixEnvVariables>,
+    pub telegram: Vec<TelegramEnvVariables>,
+    pub webhook: Vec<WebhookEnvVariables>,
+    pub email: Vec<EmailEnvVariables>,
+    pub routes: Vec<RouteConfig>,
     pub interval: u64,
+    pub timeout: u64,
+    pub max_backoff: u64,
+    pub max_report_pages: i64,
+    pub show_private_message_content: bool,
+    pub federation_gap_interval: u64,
+    pub federation_gap_grace: u64,
+    pub jwt_encryption_key: Option<String>,
+    pub outbox_max_attempts: i32,
+    pub notify_timeout: u64,
 }
 
+const DISCORD_WEBHOOK_PREFIX: &str = "https://discord.com/api/webhooks";
+
 #[derive(Clone)]
 pub struct DiscordWebhook {
     webhook_url: String,
@@ -137,6 +529,15 @@ impl DiscordWebhook {
     pub fn url(&self) -> &str {
         self.webhook_url.as_str()
     }
+
+    /// ID of the webhook without the secret token
+    pub fn id(&self) -> &str {
+        self.webhook_url[DISCORD_WEBHOOK_PREFIX.len()..]
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default()
+    }
 }
 
 impl Debug for DiscordWebhook {
@@ -150,7 +551,6 @@ impl FromStr for DiscordWebhook {
     type Err = &'static str;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        const DISCORD_WEBHOOK_PREFIX: &str = "https://discord.com/api/webhooks";
         if !s.starts_with(DISCORD_WEBHOOK_PREFIX) {
             Err("Discord webhook url must start with '{DISCORD_WEBHOOK_PREFIX}'")
         }
@@ -160,20 +560,110 @@ impl FromStr for DiscordWebhook {
     }
 }
 
-impl From<EnvArgs> for EnvVariables {
-    fn from(value: EnvArgs) -> Self {
-        let mqtt = (&value).try_into().ok();
-        let ntfy = (&value).try_into().ok();
-        EnvVariables {
-            db_host: value.db_host,
-            db_port: value.db_port,
-            db_user: value.db_user,
-            db_password: value.db_password,
-            db_name: value.db_name,
-            discord_webhook: value.discord_webhook,
+impl EnvVariables {
+    /// In the same order as the notifiers are created
+    pub fn notifier_names(&self) -> Vec<String> {
+        std::iter::once(CONSOLE_NAME.to_string())
+            .chain(self.discord.iter().map(|d| d.name.clone()))
+            .chain(self.ntfy.iter().map(|n| n.name.clone()))
+            .chain(self.mqtt.iter().map(|m| m.name.clone()))
+            .chain(self.matrix.iter().map(|m| m.name.clone()))
+            .chain(self.telegram.iter().map(|t| t.name.clone()))
+            .chain(self.webhook.iter().map(|w| w.name.clone()))
+            .chain(self.email.iter().map(|e| e.name.clone()))
+            .collect()
+    }
+
+    /// Combines the CLI options and environment variables with the config file, if one was given
+    pub fn load(value: EnvArgs) -> anyhow::Result<Self> {
+        let config = match &value.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        let mut discord = config.notifiers.discord
+            .iter()
+            .map(|c| DiscordEnvVariables::try_from(c).map_err(|e| anyhow!("Discord notifier '{}': {e}", c.name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut ntfy = config.notifiers.ntfy
+            .iter()
+            .map(|c| NtfyEnvVariables::try_from(c).map_err(|e| anyhow!("ntfy notifier '{}': {e}", c.name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut mqtt = config.notifiers.mqtt
+            .iter()
+            .map(|c| MqttEnvVariables::try_from(c).map_err(|e| anyhow!("MQTT notifier '{}': {e}", c.name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let matrix = config.notifiers.matrix
+            .iter()
+            .map(|c| MatrixEnvVariables::try_from(c).map_err(|e| anyhow!("Matrix notifier '{}': {e}", c.name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let telegram = config.notifiers.telegram
+            .iter()
+            .map(|c| TelegramEnvVariables::try_from(c).map_err(|e| anyhow!("Telegram notifier '{}': {e}", c.name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let webhook = config.notifiers.webhook
+            .iter()
+            .map(|c| WebhookEnvVariables::try_from(c).map_err(|e| anyhow!("Webhook notifier '{}': {e}", c.name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let email = config.notifiers.email
+            .iter()
+            .map(|c| EmailEnvVariables::try_from(c).map_err(|e| anyhow!("Email notifier '{}': {e}", c.name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Notifiers from CLI options and environment variables replace those with the same name in the config file
+        if let Some(webhook) = &value.discord_webhook {
+            discord.retain(|d| d.name != DEFAULT_DISCORD_NAME);
+            discord.push(DiscordEnvVariables { name: DEFAULT_DISCORD_NAME.to_string(), webhook: webhook.clone(), templates: HashMap::new() });
+        }
+        if let Ok(vars) = NtfyEnvVariables::try_from(&value) {
+            ntfy.retain(|n| n.name != DEFAULT_NTFY_NAME);
+            ntfy.push(vars);
+        }
+        if let Ok(vars) = MqttEnvVariables::try_from(&value) {
+            mqtt.retain(|m| m.name != DEFAULT_MQTT_NAME);
+            mqtt.push(vars);
+        }
+
+        // The names identify the notifiers in the outbox
+        let mut names = HashSet::from([CONSOLE_NAME]);
+        let all_names = discord.iter().map(|d| &d.name)
+            .chain(ntfy.iter().map(|n| &n.name))
+            .chain(mqtt.iter().map(|m| &m.name))
+            .chain(matrix.iter().map(|m| &m.name))
+            .chain(telegram.iter().map(|t| &t.name))
+            .chain(webhook.iter().map(|w| &w.name))
+            .chain(email.iter().map(|e| &e.name));
+        for name in all_names {
+            if !names.insert(name.as_str()) {
+                return Err(anyhow!("Notifier name '{name}' is used more than once"));
+            }
+        }
+
+        Ok(EnvVariables {
+            command: value.command,
+            db_host: value.db_host.or(config.db_host).ok_or_else(|| anyhow!("No database host set"))?,
+            db_port: value.db_port.or(config.db_port).ok_or_else(|| anyhow!("No database port set"))?,
+            db_user: value.db_user.or(config.db_user).ok_or_else(|| anyhow!("No database user set"))?,
+            db_password: value.db_password.or(config.db_password).ok_or_else(|| anyhow!("No database password set"))?,
+            db_name: value.db_name.or(config.db_name).ok_or_else(|| anyhow!("No database name set"))?,
+            discord,
             mqtt,
             ntfy,
-            interval: value.interval,
-        }
+            matrix,
+            telegram,
+            webhook,
+            email,
+            routes: config.routes,
+            interval: value.interval.or(config.interval).unwrap_or(60),
+            timeout: value.timeout.or(config.timeout).unwrap_or(30),
+            max_backoff: value.max_backoff.or(config.max_backoff).unwrap_or(3600),
+            max_report_pages: value.max_report_pages.or(config.max_report_pages).unwrap_or(10).max(1),
+            show_private_message_content: value.show_private_message_content.or(config.show_private_message_content).unwrap_or(false),
+            federation_gap_interval: value.federation_gap_interval.or(config.federation_gap_interval).unwrap_or(0),
+            federation_gap_grace: value.federation_gap_grace.or(config.federation_gap_grace).unwrap_or(3600),
+            jwt_encryption_key: value.jwt_encryption_key.or(config.jwt_encryption_key),
+            outbox_max_attempts: value.outbox_max_attempts.or(config.outbox_max_attempts).unwrap_or(10).max(1),
+            notify_timeout: value.notify_timeout.or(config.notify_timeout).unwrap_or(10).max(1),
+        })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,182 @@
+use serde_json::Value;
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Appended to text that was cut off, counts towards the limit
+pub const TRUNCATION_MARKER: &str = "…";
+
+/// Discord embed limits, counted in UTF-16 code units like Discord does.
+/// https://discord.com/developers/docs/resources/message#embed-object-embed-limits
+pub mod discord {
+    pub const USERNAME_LIMIT: usize = 80;
+    pub const TITLE_LIMIT: usize = 256;
+    pub const DESCRIPTION_LIMIT: usize = 4096;
+    pub const FIELD_NAME_LIMIT: usize = 256;
+    pub const FIELD_VALUE_LIMIT: usize = 1024;
+    pub const FIELDS_LIMIT: usize = 25;
+    /// Sum of all titles, descriptions and fields of a message
+    pub const TOTAL_LIMIT: usize = 6000;
+}
+
+/// ntfy turns messages above 4096 bytes into attachments
+pub const NTFY_MESSAGE_LIMIT: usize = 4096;
+
+/// Telegram messages are limited to 4096 UTF-16 code units after removing the HTML tags
+pub const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+/// Matrix events are limited to 64 KiB, which has to fit both the plain text and the HTML body
+pub const MATRIX_BODY_LIMIT: usize = 24 * 1024;
+
+/// The default maximum packet size of rumqttc and many brokers
+pub const MQTT_MAX_PACKET_SIZE: usize = 10 * 1024;
+
+/// Room for the topic and the packet header within [MQTT_MAX_PACKET_SIZE]
+const MQTT_PACKET_OVERHEAD: usize = 512;
+
+pub const MQTT_PAYLOAD_LIMIT: usize = MQTT_MAX_PACKET_SIZE - MQTT_PACKET_OVERHEAD;
+
+pub fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Cuts the text at a grapheme boundary, so emoji and combined characters stay intact
+fn truncate_with(text: &str, max: usize, len: fn(&str) -> usize) -> Cow<'_, str> {
+    if len(text) <= max {
+        return Cow::Borrowed(text);
+    }
+    let budget = max.saturating_sub(len(TRUNCATION_MARKER));
+    let mut used = 0;
+    let mut end = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += len(grapheme);
+        if used > budget {
+            break;
+        }
+        end = index + grapheme.len();
+    }
+    Cow::Owned(format!("{}{TRUNCATION_MARKER}", text[..end].trim_end()))
+}
+
+pub fn truncate_utf16(text: &str, max: usize) -> Cow<'_, str> {
+    truncate_with(text, max, utf16_len)
+}
+
+pub fn truncate_bytes(text: &str, max: usize) -> Cow<'_, str> {
+    truncate_with(text, max, str::len)
+}
+
+/// Escapes characters Discord would interpret as markdown, so user content shows up as written
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut line_start = true;
+    for c in text.chars() {
+        let special = match c {
+            '\\' | '*' | '_' | '~' | '`' | '|' | '[' | ']' => true,
+            // Headings, quotes and lists only start at the beginning of a line
+            '#' | '>' | '-' => line_start,
+            _ => false,
+        };
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        line_start = c == '\n' || (line_start && c.is_whitespace());
+    }
+    escaped
+}
+
+/// Removes the tags of an HTML message and decodes the entities Telegram and Matrix templates escape
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#x2f;", "/")
+        .replace("&amp;", "&")
+}
+
+/// Shortens the longest strings of the JSON until it serializes to at most `max_bytes`
+pub fn fit_json(value: &mut Value, max_bytes: usize) -> serde_json::Result<String> {
+    loop {
+        let json = serde_json::to_string(value)?;
+        if json.len() <= max_bytes {
+            return Ok(json);
+        }
+        let Some(longest) = longest_string(value) else {
+            return Ok(json);
+        };
+        // Escaped characters take more room in the JSON than in the string
+        let excess = json.len() - max_bytes;
+        let target = longest.len().saturating_sub(excess.max(TRUNCATION_MARKER.len() + 1));
+        let truncated = truncate_bytes(longest, target).into_owned();
+        if truncated.len() >= longest.len() {
+            return Ok(json);
+        }
+        *longest = truncated;
+    }
+}
+
+fn longest_string(value: &mut Value) -> Option<&mut String> {
+    match value {
+        Value::String(string) => Some(string),
+        Value::Array(values) => values.iter_mut().filter_map(longest_string).max_by_key(|s| s.len()),
+        Value::Object(map) => map.values_mut().filter_map(longest_string).max_by_key(|s| s.len()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_text_is_unchanged() {
+        assert!(matches!(truncate_utf16("Spam", 10), Cow::Borrowed("Spam")));
+    }
+
+    #[test]
+    fn truncate_marks_text() {
+        assert_eq!("Hello…", truncate_utf16("Hello world", 7));
+        assert_eq!("Hello w…", truncate_bytes("Hello world", 10));
+    }
+
+    #[test]
+    fn truncate_keeps_graphemes_intact() {
+        let family = "👨‍👩‍👧";
+        let text = format!("a{family}{family}");
+        // The family emoji is 8 UTF-16 code units, there's no room for the second one
+        assert_eq!(format!("a{family}…"), truncate_utf16(&text, 12));
+        assert_eq!("a…", truncate_utf16(&text, 8));
+        assert_eq!("e\u{301}…", truncate_bytes("e\u{301}e\u{301}e\u{301}", 7));
+    }
+
+    #[test]
+    fn escape_markdown_characters() {
+        assert_eq!("\\*bold\\* and \\_italic\\_ \\[link\\](url)", escape_markdown("*bold* and _italic_ [link](url)"));
+        assert_eq!("\\# Heading\n  \\- item\n1 - 2", escape_markdown("# Heading\n  - item\n1 - 2"));
+    }
+
+    #[test]
+    fn strip_html_tags() {
+        assert_eq!("Post report: <script> & more", strip_html("<b>Post report</b>: <a href=\"https:&#x2f;&#x2f;a.com\">&lt;script&gt; &amp; more</a>"));
+    }
+
+    #[test]
+    fn fit_json_shortens_longest_string() {
+        let mut value = serde_json::json!({ "reason": "Spam", "post": { "body": "x".repeat(2000) } });
+        let json = fit_json(&mut value, 500).unwrap();
+        assert!(json.len() <= 500);
+        assert_eq!("Spam", value["reason"]);
+        assert!(value["post"]["body"].as_str().unwrap().ends_with(TRUNCATION_MARKER));
+    }
+}
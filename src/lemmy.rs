@@ -1,15 +1,29 @@
+use crate::crypto::TokenCipher;
+use crate::db;
 use crate::models::credential::CredentialEntity;
+use crate::models::credential_role::CredentialRoleEntity;
+use crate::models::credential_token::CredentialTokenEntity;
 use crate::schema::credentials::dsl::credentials;
 use anyhow::anyhow;
 use diesel::prelude::*;
 use diesel_async::AsyncPgConnection;
 use diesel_async::RunQueryDsl;
-use lemmy_client::lemmy_api_common::comment::ListCommentReports;
+use crate::report::ReportKind;
+use crate::stupid;
+use lemmy_client::lemmy_api_common::comment::{ListCommentReports, RemoveComment, ResolveCommentReport};
+use lemmy_client::lemmy_api_common::lemmy_db_schema::newtypes::{CommentId, PostId};
 use lemmy_client::lemmy_api_common::lemmy_db_schema::sensitive::SensitiveString;
-use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView};
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
 use lemmy_client::lemmy_api_common::person::Login;
-use lemmy_client::lemmy_api_common::post::ListPostReports;
+use lemmy_client::lemmy_api_common::post::{ListPostReports, LockPost, RemovePost, ResolvePostReport};
+use lemmy_client::lemmy_api_common::private_message::{ListPrivateMessageReports, ResolvePrivateMessageReport};
+use lemmy_client::lemmy_api_common::site::ListRegistrationApplications;
+use lemmy_client::lemmy_api_common::LemmyErrorType;
 use lemmy_client::{ClientOptions, LemmyClient};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use totp_rs::{Algorithm, Secret, TOTP};
 
 pub async fn get_credentials(db_conn: &mut AsyncPgConnection) -> anyhow::Result<Vec<CredentialEntity>> {
     let creds: Vec<CredentialEntity> = credentials
@@ -20,39 +34,227 @@ pub async fn get_credentials(db_conn: &mut AsyncPgConnection) -> anyhow::Result<
     Ok(creds)
 }
 
-pub async fn collect_clients(creds: Vec<CredentialEntity>) -> anyhow::Result<Vec<(LemmyClient, String)>> {
-    let mut authenticated_clients: Vec<(LemmyClient, String)> = Vec::new();
-    for cred in creds {
-        let client_options = ClientOptions {
-            domain: cred.domain.clone(),
-            secure: true,
-        };
-        let mut client = LemmyClient::new(client_options);
-        let login_request = Login {
-            username_or_email: SensitiveString::from(cred.username.clone()),
-            password: SensitiveString::from(cred.password.clone()),
-            totp_2fa_token: None,
-        };
-        match client.login(login_request).await {
-            Ok(login_response) => {
-                let jwt = login_response.jwt.ok_or_else(|| anyhow!("JWT not found"))?.into_inner();
-                let bearer = format!("Bearer {}", jwt);
-                client.headers_mut().insert("Authorization".to_owned(), bearer);
-                authenticated_clients.push((client, cred.domain));
-            }
-            Err(e) => {
-                eprintln!("Failed to authenticate {} at {}: {}", cred.username, cred.domain, e);
-            }
+/// Lemmy refuses list requests with a limit above this value
+pub const MAX_PAGE_SIZE: i64 = 50;
+
+pub struct InstanceClient {
+    pub client: LemmyClient,
+    pub domain: String,
+    pub page_size: i64,
+    /// Only admins can list private message reports and registration applications
+    pub is_admin: bool,
+    /// Actor IDs of the communities the account moderates, empty when its role is unknown
+    pub communities: Vec<String>,
+    credential: CredentialEntity,
+    jwt: String,
+}
+
+impl InstanceClient {
+    /// Logs in again, e.g. after the stored JWT was invalidated
+    pub async fn reauthenticate(&mut self, db_conn: &mut AsyncPgConnection, cipher: Option<&TokenCipher>) -> anyhow::Result<()> {
+        let jwt = login(&self.client, &self.credential).await?;
+        set_jwt(&mut self.client, &jwt);
+        store_jwt(db_conn, cipher, &self.credential, &jwt).await;
+        self.jwt = jwt;
+        println!("Logged in again as {} at {}", self.credential.username, self.domain);
+        Ok(())
+    }
+}
+
+/// Logins of the polled accounts, so notifiers can act on reports with them
+#[derive(Clone, Default)]
+pub struct ClientRegistry {
+    jwts: Arc<RwLock<HashMap<(String, String), String>>>,
+}
+
+impl ClientRegistry {
+    /// Adds the account of the client or updates its login
+    pub fn insert(&self, instance: &InstanceClient) {
+        let key = (instance.domain.clone(), instance.credential.username.clone());
+        self.jwts.write().unwrap_or_else(PoisonError::into_inner).insert(key, instance.jwt.clone());
+    }
+
+    /// The last login of the account, even when its task was restarted
+    pub fn jwt(&self, domain: &str, username: &str) -> Option<String> {
+        self.jwts.read().unwrap_or_else(PoisonError::into_inner).get(&(domain.to_string(), username.to_string())).cloned()
+    }
+
+    pub fn remove(&self, domain: &str, username: &str) {
+        self.jwts.write().unwrap_or_else(PoisonError::into_inner).remove(&(domain.to_string(), username.to_string()));
+    }
+
+    /// Authenticated clients of every account on the instance
+    pub fn clients(&self, domain: &str) -> Vec<LemmyClient> {
+        self.jwts.read().unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|((jwt_domain, _), _)| jwt_domain == domain)
+            .map(|(_, jwt)| {
+                let mut client = LemmyClient::new(ClientOptions { domain: domain.to_string(), secure: true });
+                set_jwt(&mut client, jwt);
+                client
+            })
+            .collect()
+    }
+}
+
+/// Reuses the stored JWT when it's still valid and only logs in otherwise
+pub async fn authenticate(db_conn: &mut AsyncPgConnection, cipher: Option<&TokenCipher>, clients: &ClientRegistry, cred: CredentialEntity) -> anyhow::Result<InstanceClient> {
+    let client_options = ClientOptions {
+        domain: cred.domain.clone(),
+        secure: true,
+    };
+    let mut client = LemmyClient::new(client_options);
+
+    // Without an encryption key the token is only kept in memory, which still avoids logging in again when the credentials are reloaded
+    let stored_jwt = match cipher {
+        Some(cipher) => load_jwt(db_conn, cipher, &cred).await,
+        None => clients.jwt(&cred.domain, &cred.username),
+    };
+    // The stored JWT is only used while it's still accepted
+    let mut stored = None;
+    if let Some(jwt) = stored_jwt {
+        set_jwt(&mut client, &jwt);
+        if let Ok(role) = get_role(&client, &cred).await {
+            stored = Some((jwt, Some(role)));
+        }
+    }
+    let (jwt, role) = match stored {
+        Some(stored) => stored,
+        None => {
+            let jwt = login(&client, &cred).await?;
+            set_jwt(&mut client, &jwt);
+            store_jwt(db_conn, cipher, &cred, &jwt).await;
+            let role = match get_role(&client, &cred).await {
+                Ok(role) => Some(role),
+                Err(e) => {
+                    eprintln!("Failed to check the role of {} at {}: {}", cred.username, cred.domain, e);
+                    None
+                }
+            };
+            (jwt, role)
+        }
+    };
+    if let Some(role) = &role {
+        if let Err(e) = db::upsert_credential_role(db_conn, role).await {
+            eprintln!("Failed to store the role of {} at {}: {}", cred.username, cred.domain, e);
+        }
+    }
+    let is_admin = role.as_ref().is_some_and(|role| role.is_admin);
+    let communities = role.map(|role| role.communities).unwrap_or_default();
+
+    let page_size = cred.page_size.map(i64::from).unwrap_or(MAX_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    Ok(InstanceClient { client, domain: cred.domain.clone(), page_size, is_admin, communities, credential: cred, jwt })
+}
+
+async fn login(client: &LemmyClient, cred: &CredentialEntity) -> anyhow::Result<String> {
+    let totp_2fa_token = cred.totp_secret
+        .as_deref()
+        .map(generate_totp_token)
+        .transpose()
+        .map_err(|e| anyhow!("Failed to generate 2FA token: {e}"))?;
+    let login_request = Login {
+        username_or_email: SensitiveString::from(cred.username.clone()),
+        password: SensitiveString::from(cred.password.clone()),
+        totp_2fa_token,
+    };
+    match client.login(login_request).await {
+        Ok(login_response) => {
+            let jwt = login_response.jwt.ok_or_else(|| anyhow!("JWT not found"))?.into_inner();
+            Ok(jwt)
+        }
+        Err(e) => Err(login_error(e)),
+    }
+}
+
+/// Explains 2FA failures, the Lemmy error is kept so it can still be matched
+fn login_error(e: LemmyErrorType) -> anyhow::Error {
+    let hint = match e {
+        LemmyErrorType::MissingTotpToken => "2FA is enabled, set totp_secret for this account",
+        LemmyErrorType::IncorrectTotpToken => "2FA token was rejected, check totp_secret and the system clock",
+        _ => return anyhow!(e),
+    };
+    anyhow!(e).context(hint)
+}
+
+fn set_jwt(client: &mut LemmyClient, jwt: &str) {
+    let bearer = format!("Bearer {}", jwt);
+    client.headers_mut().insert("Authorization".to_owned(), bearer);
+}
+
+async fn load_jwt(db_conn: &mut AsyncPgConnection, cipher: &TokenCipher, cred: &CredentialEntity) -> Option<String> {
+    let encrypted = db::get_credential_token(db_conn, &cred.domain, &cred.username).await.ok()??;
+    match cipher.decrypt(&encrypted) {
+        Ok(jwt) => Some(jwt),
+        Err(e) => {
+            eprintln!("Failed to decrypt stored token of {} at {}: {}", cred.username, cred.domain, e);
+            None
         }
     }
+}
 
-    Ok(authenticated_clients)
+/// Tokens are only stored when an encryption key is configured
+async fn store_jwt(db_conn: &mut AsyncPgConnection, cipher: Option<&TokenCipher>, cred: &CredentialEntity, jwt: &str) {
+    let Some(cipher) = cipher else {
+        return;
+    };
+    let result = match cipher.encrypt(jwt) {
+        Ok(encrypted) => {
+            let token = CredentialTokenEntity {
+                domain: cred.domain.clone(),
+                username: cred.username.clone(),
+                jwt: encrypted,
+            };
+            db::upsert_credential_token(db_conn, &token).await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to store token of {} at {}: {}", cred.username, cred.domain, e);
+    }
 }
 
-pub async fn get_post_reports(client: &LemmyClient) -> anyhow::Result<Vec<PostReportView>> {
+/// Whether the request failed because the JWT is no longer accepted
+pub fn is_auth_error(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<LemmyErrorType>(), Some(LemmyErrorType::NotLoggedIn | LemmyErrorType::IncorrectLogin))
+}
+
+fn generate_totp_token(secret: &str) -> anyhow::Result<String> {
+    Ok(parse_totp_secret(secret)?.generate_current()?)
+}
+
+/// Uses the settings Lemmy uses for 2FA
+fn parse_totp_secret(secret: &str) -> anyhow::Result<TOTP> {
+    let encoded_secret = match secret.starts_with("otpauth://") {
+        true => Url::parse(secret)?
+            .query_pairs()
+            .find(|(key, _)| key == "secret")
+            .map(|(_, value)| value.into_owned())
+            .ok_or_else(|| anyhow!("TOTP URL does not contain a secret"))?,
+        false => secret.replace(' ', "").to_uppercase(),
+    };
+    let secret_bytes = Secret::Encoded(encoded_secret).to_bytes().map_err(|e| anyhow!("Invalid TOTP secret: {e:?}"))?;
+    let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, secret_bytes).map_err(|e| anyhow!("Invalid TOTP secret: {e:?}"))?;
+
+    Ok(totp)
+}
+
+/// Whether the account is an admin and which communities it moderates
+async fn get_role(client: &LemmyClient, cred: &CredentialEntity) -> anyhow::Result<CredentialRoleEntity> {
+    let site_response = client.get_site(()).await.map_err(|e| anyhow!(e))?;
+    let my_user = site_response.my_user.ok_or_else(|| anyhow!("Site response does not contain the logged in user"))?;
+
+    Ok(CredentialRoleEntity {
+        domain: cred.domain.clone(),
+        username: cred.username.clone(),
+        is_admin: my_user.local_user_view.local_user.admin,
+        communities: my_user.moderates.iter().map(|view| view.community.actor_id.to_string()).collect(),
+    })
+}
+
+pub async fn get_post_reports(client: &LemmyClient, page: i64, limit: i64) -> anyhow::Result<Vec<PostReportView>> {
     let list_post_reports_request = ListPostReports {
-        page: None,
-        limit: Some(50),
+        page: Some(page),
+        limit: Some(limit),
         unresolved_only: None,
         community_id: None,
         post_id: None,
@@ -63,11 +265,11 @@ pub async fn get_post_reports(client: &LemmyClient) -> anyhow::Result<Vec<PostRe
     Ok(post_reports)
 }
 
-pub async fn get_comment_reports(client: &LemmyClient) -> anyhow::Result<Vec<CommentReportView>> {
+pub async fn get_comment_reports(client: &LemmyClient, page: i64, limit: i64) -> anyhow::Result<Vec<CommentReportView>> {
     let list_comment_report_request = ListCommentReports  {
         comment_id: None,
-        page: None,
-        limit: Some(50),
+        page: Some(page),
+        limit: Some(limit),
         unresolved_only: None,
         community_id: None,
     };
@@ -75,4 +277,101 @@ pub async fn get_comment_reports(client: &LemmyClient) -> anyhow::Result<Vec<Com
     let comment_reports = list_comment_report_response.comment_reports;
 
     Ok(comment_reports)
+}
+
+pub async fn get_private_message_reports(client: &LemmyClient, page: i64, limit: i64) -> anyhow::Result<Vec<PrivateMessageReportView>> {
+    let list_private_message_reports_request = ListPrivateMessageReports {
+        page: Some(page),
+        limit: Some(limit),
+        unresolved_only: None,
+    };
+    let list_private_message_reports_response = client.list_private_message_reports(list_private_message_reports_request).await.map_err(|e| anyhow!(e))?;
+    let private_message_reports = list_private_message_reports_response.private_message_reports;
+
+    Ok(private_message_reports)
+}
+
+pub async fn get_registration_applications(client: &LemmyClient, page: i64, limit: i64) -> anyhow::Result<Vec<RegistrationApplicationView>> {
+    let list_registration_applications_request = ListRegistrationApplications {
+        unread_only: Some(true),
+        page: Some(page),
+        limit: Some(limit),
+    };
+    let list_registration_applications_response = client.list_registration_applications(list_registration_applications_request).await.map_err(|e| anyhow!(e))?;
+    let registration_applications = list_registration_applications_response.registration_applications;
+
+    Ok(registration_applications)
+}
+
+/// Marks the report as resolved, the account has to moderate the community
+pub async fn resolve_report(client: &LemmyClient, kind: ReportKind, report_id: i32) -> anyhow::Result<()> {
+    match kind {
+        ReportKind::Post => {
+            let request = ResolvePostReport { report_id: stupid::newtype_id(report_id), resolved: true };
+            client.resolve_post_report(request).await.map_err(|e| anyhow!(e))?;
+        }
+        ReportKind::Comment => {
+            let request = ResolveCommentReport { report_id: stupid::newtype_id(report_id), resolved: true };
+            client.resolve_comment_report(request).await.map_err(|e| anyhow!(e))?;
+        }
+        ReportKind::PrivateMessage => {
+            let request = ResolvePrivateMessageReport { report_id: stupid::newtype_id(report_id), resolved: true };
+            client.resolve_private_message_report(request).await.map_err(|e| anyhow!(e))?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn remove_post(client: &LemmyClient, post_id: PostId, reason: &str) -> anyhow::Result<()> {
+    let request = RemovePost { post_id, removed: true, reason: Some(reason.to_string()) };
+    client.remove_post(request).await.map_err(|e| anyhow!(e))?;
+    Ok(())
+}
+
+pub async fn lock_post(client: &LemmyClient, post_id: PostId) -> anyhow::Result<()> {
+    let request = LockPost { post_id, locked: true };
+    client.lock_post(request).await.map_err(|e| anyhow!(e))?;
+    Ok(())
+}
+
+pub async fn remove_comment(client: &LemmyClient, comment_id: CommentId, reason: &str) -> anyhow::Result<()> {
+    let request = RemoveComment { comment_id, removed: true, reason: Some(reason.to_string()) };
+    client.remove_comment(request).await.map_err(|e| anyhow!(e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP";
+
+    #[test]
+    fn totp_token_from_secret() {
+        let token = generate_totp_token(SECRET).unwrap();
+        assert_eq!(6, token.len());
+        assert!(token.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn auth_errors() {
+        assert!(is_auth_error(&anyhow!(LemmyErrorType::NotLoggedIn)));
+        assert!(!is_auth_error(&anyhow!(LemmyErrorType::NotAModerator)));
+        assert!(!is_auth_error(&anyhow!("Failed to fetch https://lemmy.world/post/401: not_logged_in")));
+    }
+
+    #[test]
+    fn totp_login_errors() {
+        let missing = login_error(LemmyErrorType::MissingTotpToken);
+        assert_eq!("2FA is enabled, set totp_secret for this account", missing.to_string());
+        assert!(matches!(missing.downcast_ref::<LemmyErrorType>(), Some(LemmyErrorType::MissingTotpToken)));
+        assert!(login_error(LemmyErrorType::IncorrectTotpToken).to_string().starts_with("2FA token was rejected"));
+        assert!(is_auth_error(&login_error(LemmyErrorType::IncorrectLogin)));
+    }
+
+    #[test]
+    fn totp_secret_from_url() {
+        let url = format!("otpauth://totp/lemmy.ml:user?secret={SECRET}&issuer=lemmy.ml");
+        assert_eq!(parse_totp_secret(SECRET).unwrap().generate(0), parse_totp_secret(&url).unwrap().generate(0));
+    }
 }
\ No newline at end of file
@@ -1,3 +1,5 @@
+mod config;
+mod crypto;
 mod env;
 mod schema;
 mod models;
@@ -6,18 +8,39 @@ mod stupid;
 mod util;
 mod notify;
 mod lemmy;
+mod moderation;
+mod report;
+mod outbox;
+mod routing;
+mod templates;
+mod format;
 
-use crate::db::{create_tables, establish_db_conn};
-use crate::env::{EnvArgs, EnvVariables};
-use crate::lemmy::{get_comment_reports, get_post_reports};
-use crate::notify::{collect_notifiers, NotifyReport};
-use crate::util::sleep;
+use crate::crypto::TokenCipher;
+use crate::db::{create_db_pool, create_tables, DbPool};
+use crate::env::{Command, EnvArgs, EnvVariables};
+use crate::lemmy::{get_comment_reports, get_post_reports, get_private_message_reports, get_registration_applications, ClientRegistry, InstanceClient};
+use crate::models::credential::CredentialEntity;
+use crate::models::federation_gap::FederationGap;
+use crate::models::instance_status::InstanceStatus;
+use crate::models::open_report_count::OpenReportCount;
+use crate::notify::{collect_notifiers, redact_private_message_report, NotifyReport};
+use crate::outbox::{DeliveryOptions, Notification};
+use crate::report::{detect_updates, ReportOrigin, ReportUpdate, ReportView};
+use crate::routing::{RouteTarget, Router};
+use crate::util::{backoff, sleep};
+use anyhow::anyhow;
+use async_trait::async_trait;
 use change_detector::ChangeDetector;
 use clap::Parser;
-use diesel_async::AsyncPgConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
 use dotenv::dotenv;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView};
 use lemmy_client::LemmyClient;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::task::JoinHandle;
 use tokio::{select, signal};
 use tokio_util::sync::CancellationToken;
 
@@ -25,14 +48,58 @@ use tokio_util::sync::CancellationToken;
 async fn main() -> anyhow::Result<()> {
     let _ = dotenv(); // Load env for development
     let env_args: EnvArgs = EnvArgs::parse();
-    let env_vars: EnvVariables = env_args.into();
-    let mut db_conn = establish_db_conn(&env_vars).await?;
-    create_tables(&mut db_conn).await?;
+    let env_vars = EnvVariables::load(env_args)?;
+
+    // Routing doesn't need a database connection
+    if let Some(Command::Route { report, domain }) = &env_vars.command {
+        let router = Router::new(env_vars.notifier_names(), env_vars.routes.clone())?;
+        return routing::dry_run(&router, report, domain.as_deref());
+    }
+
+    let db_pool = create_db_pool(&env_vars)?;
+    create_tables(&mut *db_pool.get().await?).await?;
+
+    if let Some(command) = &env_vars.command {
+        return match command {
+            Command::Outbox { command } => outbox::run_command(&mut *db_pool.get().await?, command).await,
+            Command::Route { .. } => Ok(()),
+        };
+    }
+
     let token = CancellationToken::new();
 
-    let notifiers: Vec<Box<dyn NotifyReport>> = collect_notifiers(&env_vars, token.clone()).await?;
+    // Filled by the instance tasks once their accounts are logged in
+    let clients = ClientRegistry::default();
+    let notifiers: Arc<Vec<Box<dyn NotifyReport>>> = Arc::new(collect_notifiers(&env_vars, &db_pool, &clients, token.clone()).await?);
+    let router = Arc::new(Router::new(env_vars.notifier_names(), env_vars.routes.clone())?);
+
+    let federation_gaps_task = match env_vars.federation_gap_interval {
+        0 => None,
+        interval => Some(tokio::spawn(report_federation_gaps(token.clone(), interval, env_vars.federation_gap_grace, db_pool.clone(), router.clone()))),
+    };
+
+    let delivery_options = DeliveryOptions {
+        max_attempts: env_vars.outbox_max_attempts,
+        max_backoff: env_vars.max_backoff,
+        timeout: Duration::from_secs(env_vars.notify_timeout),
+    };
+    let outbox_task = tokio::spawn(outbox::deliver_notifications(token.clone(), db_pool.clone(), notifiers.clone(), delivery_options));
 
-    let mut check_reports_task = tokio::spawn(check_all_reports(token.clone(), env_vars.interval, db_conn, notifiers));
+    // Only MQTT notifiers publish the state, so the queries are skipped without them
+    let state_task = match env_vars.mqtt.is_empty() {
+        true => None,
+        false => Some(tokio::spawn(publish_state(token.clone(), env_vars.interval, db_pool.clone(), notifiers.clone()))),
+    };
+
+    let cipher = env_vars.jwt_encryption_key.as_deref().map(TokenCipher::new).map(Arc::new);
+    let options = PollOptions {
+        interval: env_vars.interval,
+        timeout: env_vars.timeout,
+        max_backoff: env_vars.max_backoff,
+        max_pages: env_vars.max_report_pages,
+        show_private_message_content: env_vars.show_private_message_content,
+    };
+    let mut check_reports_task = tokio::spawn(check_all_reports(token.clone(), options, db_pool, cipher, router, clients));
 
     select! {
         _ = signal::ctrl_c() => {
@@ -52,74 +119,434 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    token.cancel();
+    if let Some(task) = federation_gaps_task {
+        if let Err(e) = task.await {
+            eprintln!("Error during federation gap task shutdown: {}", e);
+        }
+    }
+
+    if let Err(e) = outbox_task.await {
+        eprintln!("Error during outbox task shutdown: {}", e);
+    }
+
+    if let Some(task) = state_task {
+        if let Err(e) = task.await {
+            eprintln!("Error during state task shutdown: {}", e);
+        }
+    }
+
     println!("Shutdown completed");
 
     Ok(())
 }
 
-async fn check_all_reports(token: CancellationToken, interval: u64, mut db_conn: AsyncPgConnection, notifiers: Vec<Box<dyn NotifyReport>>) -> anyhow::Result<()> {
+#[derive(Clone)]
+struct PollOptions {
+    interval: u64,
+    timeout: u64,
+    max_backoff: u64,
+    max_pages: i64,
+    show_private_message_content: bool,
+}
+
+struct InstanceTask {
+    credential: CredentialEntity,
+    token: CancellationToken,
+    handle: JoinHandle<()>,
+}
+
+async fn check_all_reports(token: CancellationToken, options: PollOptions, db_pool: DbPool, cipher: Option<Arc<TokenCipher>>, router: Arc<Router>, clients: ClientRegistry) -> anyhow::Result<()> {
     let mut credentials_change_detector = ChangeDetector::new();
-    let mut clients: Vec<(LemmyClient, String)> = Vec::new();
+    let mut tasks: HashMap<(String, String), InstanceTask> = HashMap::new();
 
     while !token.is_cancelled() {
-        // Credentials are fetched again before making the requests to allow adding/removing clients while in use
-        let client_credentials = lemmy::get_credentials(&mut db_conn).await?;
-        // Simple change detection is used to avoid hitting the login rate limit
+        // Credentials are fetched again periodically to allow adding/removing clients while in use
+        let client_credentials = lemmy::get_credentials(&mut *db_pool.get().await?).await?;
         if let Some(creds) = credentials_change_detector.detect_owned(client_credentials) {
-            clients = lemmy::collect_clients(creds).await?;
-            println!("Using {count} clients", count = clients.len());
+            let mut current = HashSet::new();
+            for cred in creds {
+                let key = (cred.domain.clone(), cred.username.clone());
+                current.insert(key.clone());
+                // Only instances of which the credentials changed are restarted, to avoid hitting the login rate limit
+                if tasks.get(&key).is_some_and(|task| task.credential == cred) {
+                    continue;
+                }
+                // The old task has to stop first, otherwise both could store and queue the same reports
+                if let Some(task) = tasks.remove(&key) {
+                    task.token.cancel();
+                    if let Err(e) = task.handle.await {
+                        eprintln!("Error during instance task shutdown: {}", e);
+                    }
+                }
+                let instance_token = token.child_token();
+                let handle = tokio::spawn(poll_instance(instance_token.clone(), cred.clone(), options.clone(), db_pool.clone(), cipher.clone(), router.clone(), clients.clone()));
+                tasks.insert(key, InstanceTask { credential: cred, token: instance_token, handle });
+            }
+            let removed = tasks.keys().filter(|key| !current.contains(*key)).cloned().collect::<Vec<_>>();
+            for key in removed {
+                if let Some(task) = tasks.remove(&key) {
+                    task.token.cancel();
+                    if let Err(e) = task.handle.await {
+                        eprintln!("Error during instance task shutdown: {}", e);
+                    }
+                }
+                // Only after the task stopped, so it can't add the login again
+                clients.remove(&key.0, &key.1);
+            }
+            println!("Polling {count} instances", count = tasks.len());
+        }
+
+        sleep(Duration::from_secs(options.interval), &token).await;
+    }
+
+    for task in tasks.into_values() {
+        if let Err(e) = task.handle.await {
+            eprintln!("Error during instance task shutdown: {}", e);
         }
+    }
 
-        for (lemmy_client, domain) in &clients {
-            match check_client_reports(&mut db_conn, lemmy_client, domain, &notifiers).await {
-                Ok(_) => {}
-                Err(err) => {
-                    println!("Failed to check reports on {domain}: {err}");
+    Ok(())
+}
+
+/// Polls a single instance on its own schedule, so a slow or unreachable instance doesn't delay the others
+async fn poll_instance(token: CancellationToken, cred: CredentialEntity, options: PollOptions, db_pool: DbPool, cipher: Option<Arc<TokenCipher>>, router: Arc<Router>, clients: ClientRegistry) {
+    let domain = cred.domain.clone();
+    let interval = cred.interval_seconds.map(|v| v.max(1) as u64).unwrap_or(options.interval);
+    let timeout = Duration::from_secs(cred.timeout_seconds.map(|v| v.max(1) as u64).unwrap_or(options.timeout));
+    let mut instance: Option<InstanceClient> = None;
+    let mut failures: u32 = 0;
+
+    while !token.is_cancelled() {
+        let result = tokio::time::timeout(timeout, check_instance(&db_pool, &cred, &mut instance, cipher.as_deref(), &options, &router, &clients))
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("Timed out after {}s", timeout.as_secs())));
+        let logged_in = instance.is_some() && !result.as_ref().is_err_and(lemmy::is_auth_error);
+        let error = result.as_ref().err().map(|err| err.to_string());
+        if let Err(err) = record_poll(&db_pool, &cred, logged_in, error.as_deref()).await {
+            println!("Failed to record the poll of {domain}: {err}");
+        }
+        let wait = match result {
+            Ok(_) => {
+                failures = 0;
+                interval
+            }
+            Err(err) => {
+                failures = failures.saturating_add(1);
+                let wait = backoff(interval, failures, options.max_backoff);
+                println!("Failed to check reports on {domain}: {err}, retrying in {wait}s");
+                wait
+            }
+        };
+
+        sleep(Duration::from_secs(wait), &token).await;
+    }
+}
+
+async fn record_poll(db_pool: &DbPool, cred: &CredentialEntity, logged_in: bool, error: Option<&str>) -> anyhow::Result<()> {
+    let mut db_conn = db_pool.get().await?;
+    db::record_instance_poll(&mut db_conn, &cred.domain, &cred.username, logged_in, error).await
+}
+
+async fn check_instance(db_pool: &DbPool, cred: &CredentialEntity, instance: &mut Option<InstanceClient>, cipher: Option<&TokenCipher>, options: &PollOptions, router: &Router, clients: &ClientRegistry) -> anyhow::Result<()> {
+    let mut db_conn = db_pool.get().await?;
+    if instance.is_none() {
+        let authenticated = lemmy::authenticate(&mut db_conn, cipher, clients, cred.clone()).await?;
+        clients.insert(&authenticated);
+        *instance = Some(authenticated);
+    }
+    let Some(instance) = instance.as_mut() else {
+        return Ok(());
+    };
+
+    let mut result = check_client_reports(&mut db_conn, instance, options.max_pages, options.show_private_message_content, router).await;
+    // Tokens can be invalidated while in use, in which case only this client logs in again
+    if result.as_ref().is_err_and(lemmy::is_auth_error) {
+        instance.reauthenticate(&mut db_conn, cipher).await?;
+        clients.insert(instance);
+        result = check_client_reports(&mut db_conn, instance, options.max_pages, options.show_private_message_content, router).await;
+    }
+    result
+}
+
+async fn report_federation_gaps(token: CancellationToken, interval: u64, grace: u64, db_pool: DbPool, router: Arc<Router>) {
+    // The first summary is sent after a full interval, so it covers a complete window
+    sleep(Duration::from_secs(interval), &token).await;
+    while !token.is_cancelled() {
+        let gaps = match db_pool.get().await {
+            Ok(mut db_conn) => db::get_federation_gaps(&mut db_conn, interval, grace).await,
+            Err(err) => Err(err.into()),
+        };
+        match gaps {
+            Ok(gaps) if gaps.is_empty() => {}
+            Ok(gaps) => {
+                if let Err(err) = queue_federation_gaps(&db_pool, &router, gaps).await {
+                    println!("Failed to queue federation gaps: {err}");
                 }
-            };
+            }
+            Err(err) => {
+                println!("Failed to detect federation gaps: {err}");
+            }
         }
 
-        println!("Waiting {interval}s before checking again...");
         sleep(Duration::from_secs(interval), &token).await;
     }
+}
 
-    Ok(())
+/// Publishes the open reports and the status of every instance, for notifiers that keep a state like MQTT
+async fn publish_state(token: CancellationToken, interval: u64, db_pool: DbPool, notifiers: Arc<Vec<Box<dyn NotifyReport>>>) {
+    while !token.is_cancelled() {
+        let state = match db_pool.get().await {
+            Ok(mut db_conn) => get_state(&mut db_conn).await,
+            Err(err) => Err(err.into()),
+        };
+        match state {
+            Ok((statuses, counts)) => {
+                for notifier in notifiers.iter() {
+                    if let Err(err) = notifier.notify_instance_statuses(&statuses).await {
+                        println!("Failed to publish instance statuses with notifier '{}': {err}", notifier.name());
+                    }
+                    if let Err(err) = notifier.notify_open_reports(&counts).await {
+                        println!("Failed to publish open reports with notifier '{}': {err}", notifier.name());
+                    }
+                }
+            }
+            Err(err) => {
+                println!("Failed to collect the state: {err}");
+            }
+        }
+
+        sleep(Duration::from_secs(interval), &token).await;
+    }
+}
+
+async fn get_state(db_conn: &mut AsyncPgConnection) -> anyhow::Result<(Vec<InstanceStatus>, Vec<OpenReportCount>)> {
+    let statuses = db::get_instance_statuses(db_conn).await?;
+    let counts = db::get_open_report_counts(db_conn).await?;
+    Ok((statuses, counts))
+}
+
+async fn queue_federation_gaps(db_pool: &DbPool, router: &Router, gaps: Vec<FederationGap>) -> anyhow::Result<()> {
+    let mut db_conn = db_pool.get().await?;
+    let notifier_names = router.route(&RouteTarget::federation_gaps());
+    outbox::enqueue(&mut db_conn, &notifier_names, &Notification::FederationGaps { gaps }).await
+}
+
+/// The parts of polling that differ between the kinds of reports
+#[async_trait]
+trait PolledReport: ReportView + 'static {
+    async fn fetch(client: &LemmyClient, page: i64, limit: i64) -> anyhow::Result<Vec<Self>>;
+    /// Stored data of the reports with the IDs which are already known
+    async fn get_known(db_conn: &mut AsyncPgConnection, domain: &str, ids: Vec<i32>) -> anyhow::Result<HashMap<i32, serde_json::Value>>;
+    async fn insert(db_conn: &mut AsyncPgConnection, domain: &str, reports: &[Self]) -> anyhow::Result<()>;
+    fn notification(domain: &str, report: &Self, show_private_message_content: bool) -> Notification;
+}
+
+#[async_trait]
+impl PolledReport for PostReportView {
+    async fn fetch(client: &LemmyClient, page: i64, limit: i64) -> anyhow::Result<Vec<Self>> {
+        get_post_reports(client, page, limit).await
+    }
+
+    async fn get_known(db_conn: &mut AsyncPgConnection, domain: &str, ids: Vec<i32>) -> anyhow::Result<HashMap<i32, serde_json::Value>> {
+        Ok(db::get_known_post_reports(db_conn, domain, ids).await?.into_iter().map(|r| (r.id, r.data)).collect())
+    }
+
+    async fn insert(db_conn: &mut AsyncPgConnection, domain: &str, reports: &[Self]) -> anyhow::Result<()> {
+        db::insert_post_reports(db_conn, domain, reports).await
+    }
+
+    fn notification(domain: &str, report: &Self, _show_private_message_content: bool) -> Notification {
+        Notification::Post { source_domain: domain.to_string(), report: report.clone() }
+    }
 }
 
-async fn check_client_reports(db_conn: &mut AsyncPgConnection, lemmy_client: &LemmyClient, domain: &str, notifiers: &Vec<Box<dyn NotifyReport>>) -> anyhow::Result<()> {
-    let post_reports = get_post_reports(lemmy_client).await?;
-    let post_report_ids = post_reports.iter().map(|v| stupid::extract_post_report_id(v.post_report.id)).collect::<Vec<_>>();
-    let known_post_report_ids = db::get_known_post_ids(db_conn, post_report_ids).await?;
+#[async_trait]
+impl PolledReport for CommentReportView {
+    async fn fetch(client: &LemmyClient, page: i64, limit: i64) -> anyhow::Result<Vec<Self>> {
+        get_comment_reports(client, page, limit).await
+    }
+
+    async fn get_known(db_conn: &mut AsyncPgConnection, domain: &str, ids: Vec<i32>) -> anyhow::Result<HashMap<i32, serde_json::Value>> {
+        Ok(db::get_known_comment_reports(db_conn, domain, ids).await?.into_iter().map(|r| (r.id, r.data)).collect())
+    }
+
+    async fn insert(db_conn: &mut AsyncPgConnection, domain: &str, reports: &[Self]) -> anyhow::Result<()> {
+        db::insert_comment_reports(db_conn, domain, reports).await
+    }
+
+    fn notification(domain: &str, report: &Self, _show_private_message_content: bool) -> Notification {
+        Notification::Comment { source_domain: domain.to_string(), report: report.clone() }
+    }
+}
 
-    let new_post_reports = post_reports
-        .iter()
-        .filter(|v| !known_post_report_ids.contains(&stupid::extract_post_report_id(v.post_report.id)) && !v.post_report.resolved)
-        .cloned()
-        .collect::<Vec<_>>();
-    db::insert_post_reports(db_conn, domain, &new_post_reports).await?;
+#[async_trait]
+impl PolledReport for PrivateMessageReportView {
+    async fn fetch(client: &LemmyClient, page: i64, limit: i64) -> anyhow::Result<Vec<Self>> {
+        get_private_message_reports(client, page, limit).await
+    }
+
+    async fn get_known(db_conn: &mut AsyncPgConnection, domain: &str, ids: Vec<i32>) -> anyhow::Result<HashMap<i32, serde_json::Value>> {
+        Ok(db::get_known_private_message_reports(db_conn, domain, ids).await?.into_iter().map(|r| (r.id, r.data)).collect())
+    }
+
+    async fn insert(db_conn: &mut AsyncPgConnection, domain: &str, reports: &[Self]) -> anyhow::Result<()> {
+        db::insert_private_message_reports(db_conn, domain, reports).await
+    }
+
+    fn notification(domain: &str, report: &Self, show_private_message_content: bool) -> Notification {
+        let report = match show_private_message_content {
+            true => report.clone(),
+            false => redact_private_message_report(report),
+        };
+        Notification::PrivateMessage { source_domain: domain.to_string(), report }
+    }
+}
 
-    for post_report in &new_post_reports {
-        for notifier in notifiers {
-            notifier.notify_post(domain, post_report).await?;
+/// Pages are walked until one without new or changed reports is returned after every stored open report was seen again,
+/// so resolving a report further down is noticed as well. A partial page or the page limit ends the walk early.
+/// Stored open reports that weren't seen by then are marked as gone, so later polls don't walk every page waiting for them.
+async fn check_reports<T: PolledReport>(db_conn: &mut AsyncPgConnection, instance: &InstanceClient, max_pages: i64, show_private_message_content: bool, router: &Router) -> anyhow::Result<()> {
+    let domain = instance.domain.as_str();
+    // Moderators only see the reports of their communities, other accounts on the instance may see the rest
+    let communities = (!instance.is_admin).then_some(instance.communities.as_slice());
+    let mut unseen_report_ids = db::get_open_report_ids(db_conn, T::KIND, domain, communities).await?.into_iter().collect::<HashSet<_>>();
+    for page in 1..=max_pages {
+        let reports = T::fetch(&instance.client, page, instance.page_size).await?;
+        let report_ids = reports.iter().map(|v| v.report_id()).collect::<Vec<_>>();
+        for id in &report_ids {
+            unseen_report_ids.remove(id);
         }
+        let known_reports = T::get_known(db_conn, domain, report_ids).await?;
+        // Reports that were gone are listed again, e.g. when the account moderates their community again
+        let known_report_ids = known_reports.keys().copied().collect::<Vec<_>>();
+        db::set_reports_gone(db_conn, T::KIND, domain, &known_report_ids, false).await?;
+
+        let new_reports = reports
+            .iter()
+            .filter(|v| !known_reports.contains_key(&v.report_id()) && !v.resolved())
+            .cloned()
+            .collect::<Vec<_>>();
+        // Storing the reports and queueing their notifications together ensures no report is known without being sent
+        let new_reports = &new_reports;
+        db_conn.transaction::<_, anyhow::Error, _>(|db_conn| async move {
+            T::insert(db_conn, domain, new_reports).await?;
+            for report in new_reports {
+                let notification = T::notification(domain, report, show_private_message_content);
+                queue_new_report(db_conn, domain, report, notification, router).await?;
+            }
+            Ok(())
+        }.scope_boxed()).await?;
+
+        let report_updates = detect_updates(domain, &reports, &known_reports);
+        handle_report_updates(db_conn, domain, &report_updates, router).await?;
+
+        if (new_reports.is_empty() && report_updates.is_empty() && unseen_report_ids.is_empty()) || (reports.len() as i64) < instance.page_size {
+            break;
+        }
+    }
+
+    if !unseen_report_ids.is_empty() {
+        println!("{} open {} reports of {domain} aren't listed anymore", unseen_report_ids.len(), T::KIND);
+        let gone_report_ids = unseen_report_ids.into_iter().collect::<Vec<_>>();
+        db::set_reports_gone(db_conn, T::KIND, domain, &gone_report_ids, true).await?;
+    }
+    Ok(())
+}
+
+async fn check_client_reports(db_conn: &mut AsyncPgConnection, instance: &InstanceClient, max_pages: i64, show_private_message_content: bool, router: &Router) -> anyhow::Result<()> {
+    let domain = instance.domain.as_str();
+
+    check_reports::<PostReportView>(db_conn, instance, max_pages, show_private_message_content, router).await?;
+    check_reports::<CommentReportView>(db_conn, instance, max_pages, show_private_message_content, router).await?;
+    // Only admins can list private message reports, moderators would fail every poll
+    if instance.is_admin {
+        check_reports::<PrivateMessageReportView>(db_conn, instance, max_pages, show_private_message_content, router).await?;
     }
 
-    let comment_reports = get_comment_reports(lemmy_client).await?;
-    let comment_report_ids = comment_reports.iter().map(|v| stupid::extract_comment_report_id(v.comment_report.id)).collect::<Vec<_>>();
-    let known_comment_report_ids = db::get_known_comment_ids(db_conn, comment_report_ids).await?;
+    if instance.is_admin {
+        for page in 1..=max_pages {
+            let applications = get_registration_applications(&instance.client, page, instance.page_size).await?;
+            let application_ids = applications.iter().map(|v| stupid::extract_id(v.registration_application.id)).collect::<Vec<_>>();
+            let known_application_ids = db::get_known_registration_application_ids(db_conn, domain, application_ids).await?;
 
-    let new_comment_reports = comment_reports
-        .iter()
-        .filter(|v| !known_comment_report_ids.contains(&stupid::extract_comment_report_id(v.comment_report.id)) && !v.comment_report.resolved)
-        .cloned()
-        .collect::<Vec<_>>();
-    db::insert_comment_reports(db_conn, domain, &new_comment_reports).await?;
+            let new_applications = applications
+                .iter()
+                .filter(|v| !known_application_ids.contains(&stupid::extract_id(v.registration_application.id)))
+                .cloned()
+                .collect::<Vec<_>>();
+            let new_applications = &new_applications;
+            db_conn.transaction::<_, anyhow::Error, _>(|db_conn| async move {
+                db::insert_registration_applications(db_conn, domain, new_applications).await?;
+                for application in new_applications {
+                    let notification = Notification::RegistrationApplication { source_domain: domain.to_string(), application: application.clone() };
+                    let notifier_names = router.route(&RouteTarget::from_registration_application(domain, application));
+                    outbox::enqueue(db_conn, &notifier_names, &notification).await?;
+                }
+                Ok(())
+            }.scope_boxed()).await?;
 
-    for comment_report in &new_comment_reports {
-        for notifier in notifiers {
-            notifier.notify_comment(domain, comment_report).await?;
+            if new_applications.is_empty() || (applications.len() as i64) < instance.page_size {
+                break;
+            }
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+async fn handle_report_updates<T: ReportView>(db_conn: &mut AsyncPgConnection, domain: &str, updates: &[(T, ReportUpdate)], router: &Router) -> anyhow::Result<()> {
+    for (view, update) in updates {
+        db_conn.transaction::<_, anyhow::Error, _>(|db_conn| async move {
+            db::update_report_data(db_conn, update.kind, domain, update.report_id, serde_json::to_value(view)?).await?;
+            let history_id = db::insert_report_history(db_conn, domain, update).await?;
+            // Only changes of the report which was originally sent are forwarded, otherwise every copy would send the same update again
+            if find_report_origin(db_conn, domain, view).await?.is_some() {
+                return Ok(());
+            }
+            // Routed like the report, so the update reaches the notifiers that received it
+            let notifier_names = router.route(&RouteTarget::from_view(domain, view));
+            let update = ReportUpdate { history_id: Some(history_id), ..update.clone() };
+            outbox::enqueue(db_conn, &notifier_names, &Notification::Update { source_domain: domain.to_string(), update }).await
+        }.scope_boxed()).await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the copy of the report that was seen first, or `None` if that is the given report itself
+async fn find_report_origin<T: ReportView>(db_conn: &mut AsyncPgConnection, domain: &str, view: &T) -> anyhow::Result<Option<ReportOrigin>> {
+    let copies = db::get_report_copies(db_conn, T::KIND, &view.federation_key()).await?;
+    let Some((origin_domain, origin_report_id)) = copies.first().cloned() else {
+        return Ok(None);
+    };
+    if origin_domain == domain && origin_report_id == view.report_id() {
+        return Ok(None);
+    }
+    let mut seen_on: Vec<String> = Vec::new();
+    for (copy_domain, _) in copies {
+        if !seen_on.contains(&copy_domain) {
+            seen_on.push(copy_domain);
+        }
+    }
+
+    Ok(Some(ReportOrigin { domain: origin_domain, report_id: origin_report_id, seen_on }))
+}
+
+/// Queues the notification of a new report, or lets the notifiers know when it's a copy of one that was already sent from another instance
+async fn queue_new_report<T: ReportView>(db_conn: &mut AsyncPgConnection, domain: &str, view: &T, notification: Notification, router: &Router) -> anyhow::Result<()> {
+    let (notifier_names, notification) = match find_report_origin(db_conn, domain, view).await? {
+        Some(origin) => {
+            let notifier_names = router.route(&RouteTarget::from_view(&origin.domain, view));
+            let copy = Notification::Copy {
+                origin_domain: origin.domain,
+                kind: T::KIND,
+                origin_report_id: origin.report_id,
+                seen_on: origin.seen_on,
+            };
+            (notifier_names, copy)
+        }
+        None => (router.route(&RouteTarget::from_view(domain, view)), notification),
+    };
+    outbox::enqueue(db_conn, &notifier_names, &notification).await
+}
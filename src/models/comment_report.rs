@@ -9,5 +9,7 @@ pub struct CommentReportEntity {
     pub domain: String,
     pub id: i32,
     pub data: serde_json::Value,
+    /// Identifies copies of the same report on other instances
+    pub report_key: Option<String>,
 }
 
@@ -1,7 +1,7 @@
 use diesel::prelude::*;
 use crate::schema::credentials;
 
-#[derive(Clone, Queryable, Identifiable, Selectable, Hash)]
+#[derive(Clone, Queryable, Identifiable, Selectable, Hash, PartialEq, Eq)]
 #[diesel(primary_key(domain, username))]
 #[diesel(table_name = credentials)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -9,5 +9,12 @@ pub struct CredentialEntity {
     pub domain: String,
     pub username: String,
     pub password: String,
+    pub page_size: Option<i32>,
+    /// Base32 secret or `otpauth://` URL for accounts with 2FA enabled
+    pub totp_secret: Option<String>,
+    /// Overrides the global interval for this instance
+    pub interval_seconds: Option<i32>,
+    /// Overrides the global timeout for this instance
+    pub timeout_seconds: Option<i32>,
 }
 
@@ -0,0 +1,16 @@
+use diesel::prelude::*;
+use crate::schema::credential_roles;
+
+/// Decides which reports the account can see, and with that which reports the instance is expected to receive
+#[derive(Clone, Queryable, Identifiable, Selectable, Insertable)]
+#[diesel(primary_key(domain, username))]
+#[diesel(table_name = credential_roles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CredentialRoleEntity {
+    pub domain: String,
+    pub username: String,
+    /// Admins see the reports of every local community
+    pub is_admin: bool,
+    /// Actor IDs of the moderated communities
+    pub communities: Vec<String>,
+}
@@ -0,0 +1,13 @@
+use diesel::prelude::*;
+use crate::schema::credential_tokens;
+
+#[derive(Clone, Queryable, Identifiable, Selectable, Insertable)]
+#[diesel(primary_key(domain, username))]
+#[diesel(table_name = credential_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CredentialTokenEntity {
+    pub domain: String,
+    pub username: String,
+    /// Encrypted JWT
+    pub jwt: String,
+}
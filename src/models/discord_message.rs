@@ -0,0 +1,14 @@
+use diesel::prelude::*;
+use crate::schema::discord_messages;
+
+#[derive(Clone, Queryable, Identifiable, Selectable, Insertable)]
+#[diesel(primary_key(domain, kind, report_id, webhook_id))]
+#[diesel(table_name = discord_messages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DiscordMessageEntity {
+    pub domain: String,
+    pub kind: String,
+    pub report_id: i32,
+    pub webhook_id: String,
+    pub message_id: String,
+}
@@ -0,0 +1,24 @@
+use diesel::prelude::*;
+use crate::schema::email_digest_entries;
+
+/// A rendered notification waiting for the next digest mail of its notifier
+#[derive(Clone, Queryable, Selectable)]
+#[diesel(table_name = email_digest_entries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct EmailDigestEntryEntity {
+    pub id: i64,
+    pub notifier: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+#[derive(Clone, Insertable)]
+#[diesel(table_name = email_digest_entries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewEmailDigestEntryEntity {
+    pub notifier: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
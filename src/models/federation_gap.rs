@@ -0,0 +1,22 @@
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Double, Nullable, Text};
+use serde::{Deserialize, Serialize};
+
+/// Federation of reports from one instance to another instance which has an account that can see them
+#[derive(Clone, Debug, QueryableByName, Serialize, Deserialize)]
+pub struct FederationGap {
+    #[diesel(sql_type = Text)]
+    pub source_domain: String,
+    #[diesel(sql_type = Text)]
+    pub target_domain: String,
+    /// Reports that never showed up on the target instance
+    #[diesel(sql_type = BigInt)]
+    pub missing: i64,
+    /// Reports that did show up on the target instance
+    #[diesel(sql_type = BigInt)]
+    pub federated: i64,
+    #[diesel(sql_type = Nullable<Double>)]
+    pub average_lag_seconds: Option<f64>,
+    #[diesel(sql_type = Nullable<Double>)]
+    pub max_lag_seconds: Option<f64>,
+}
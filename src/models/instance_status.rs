@@ -0,0 +1,20 @@
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Nullable, Text};
+use serde::Serialize;
+
+/// Result of the latest polls of an instance, combined over all of its accounts
+#[derive(Clone, Debug, QueryableByName, Serialize)]
+pub struct InstanceStatus {
+    #[diesel(sql_type = Text)]
+    pub domain: String,
+    /// RFC 3339, `None` until the instance was polled
+    #[diesel(sql_type = Nullable<Text>)]
+    pub last_poll: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub last_success: Option<String>,
+    /// Whether every account of the instance is logged in
+    #[diesel(sql_type = Nullable<Bool>)]
+    pub logged_in: Option<bool>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub last_error: Option<String>,
+}
@@ -0,0 +1,17 @@
+use diesel::prelude::*;
+use crate::schema::matrix_messages;
+
+/// A report sent to a Matrix room, reports of the same post share the thread started by the first one
+#[derive(Clone, Queryable, Identifiable, Selectable, Insertable)]
+#[diesel(primary_key(domain, kind, report_id, room_id))]
+#[diesel(table_name = matrix_messages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MatrixMessageEntity {
+    pub domain: String,
+    pub kind: String,
+    pub report_id: i32,
+    pub room_id: String,
+    pub post_id: Option<i32>,
+    pub event_id: String,
+    pub thread_root: Option<String>,
+}
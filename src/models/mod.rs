@@ -1,3 +1,15 @@
 pub mod credential;
+pub mod credential_token;
+pub mod credential_role;
 pub mod post_report;
-pub mod comment_report;
\ No newline at end of file
+pub mod comment_report;
+pub mod private_message_report;
+pub mod registration_application;
+pub mod report_history;
+pub mod discord_message;
+pub mod matrix_message;
+pub mod email_digest_entry;
+pub mod federation_gap;
+pub mod instance_status;
+pub mod open_report_count;
+pub mod outbox_notification;
@@ -0,0 +1,19 @@
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable, Text};
+use serde::Serialize;
+
+/// Unresolved post and comment reports of a community as seen on one instance
+#[derive(Clone, Debug, QueryableByName, Serialize)]
+pub struct OpenReportCount {
+    #[diesel(sql_type = Text)]
+    pub domain: String,
+    /// `None` for a polled instance without any community reports, its counts are 0
+    #[diesel(sql_type = Nullable<Text>)]
+    pub community_name: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub community_actor_id: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    pub posts: i64,
+    #[diesel(sql_type = BigInt)]
+    pub comments: i64,
+}
@@ -0,0 +1,29 @@
+use diesel::prelude::*;
+use crate::schema::notification_outbox;
+
+#[derive(Clone, Queryable, Selectable)]
+#[diesel(table_name = notification_outbox)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct OutboxNotificationEntity {
+    pub id: i64,
+    /// Name of the notifier the notification is delivered to
+    pub notifier: String,
+    pub event: String,
+    pub source_domain: Option<String>,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Insertable)]
+#[diesel(table_name = notification_outbox)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewOutboxNotificationEntity {
+    pub notifier: String,
+    pub event: String,
+    pub source_domain: Option<String>,
+    pub payload: serde_json::Value,
+    /// Keeps the notifications of a report in order, see [`Notification::report`](crate::outbox::Notification::report)
+    pub report: Option<String>,
+}
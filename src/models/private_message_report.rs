@@ -0,0 +1,14 @@
+use diesel::prelude::*;
+use crate::schema::private_message_reports;
+
+#[derive(Clone, Queryable, Identifiable, Selectable, Insertable)]
+#[diesel(primary_key(domain, id))]
+#[diesel(table_name = private_message_reports)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PrivateMessageReportEntity {
+    pub domain: String,
+    pub id: i32,
+    pub data: serde_json::Value,
+    /// Identifies copies of the same report on other instances
+    pub report_key: Option<String>,
+}
@@ -0,0 +1,12 @@
+use diesel::prelude::*;
+use crate::schema::registration_applications;
+
+#[derive(Clone, Queryable, Identifiable, Selectable, Insertable)]
+#[diesel(primary_key(domain, id))]
+#[diesel(table_name = registration_applications)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RegistrationApplicationEntity {
+    pub domain: String,
+    pub id: i32,
+    pub data: serde_json::Value,
+}
@@ -0,0 +1,14 @@
+use diesel::prelude::*;
+use crate::schema::report_history;
+
+#[derive(Clone, Insertable)]
+#[diesel(table_name = report_history)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewReportHistoryEntity {
+    pub domain: String,
+    pub kind: String,
+    pub report_id: i32,
+    pub resolved: bool,
+    pub resolver_id: Option<i32>,
+    pub content_removed: bool,
+}
@@ -0,0 +1,65 @@
+use crate::db;
+use crate::db::DbPool;
+use crate::lemmy;
+use crate::lemmy::ClientRegistry;
+use crate::report::{ReportKind, ReportView};
+use anyhow::anyhow;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView};
+use lemmy_client::LemmyClient;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// Actions on reports that can be triggered from notifiers, e.g. with Telegram buttons or MQTT commands
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Resolve,
+    /// Removes the reported post or comment
+    Remove,
+    /// Locks the reported post or the post of the reported comment
+    Lock,
+}
+
+/// Any of the accounts on the instance may moderate the community, so each is tried until one succeeds
+pub async fn perform(db_pool: &DbPool, clients: &ClientRegistry, domain: &str, kind: ReportKind, report_id: i32, action: ModerationAction) -> anyhow::Result<()> {
+    let mut result = Err(anyhow!("No account is logged in at {domain}"));
+    for client in &clients.clients(domain) {
+        result = perform_with(db_pool, client, domain, kind, report_id, action).await;
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+async fn perform_with(db_pool: &DbPool, client: &LemmyClient, domain: &str, kind: ReportKind, report_id: i32, action: ModerationAction) -> anyhow::Result<()> {
+    if action == ModerationAction::Resolve {
+        return lemmy::resolve_report(client, kind, report_id).await;
+    }
+    // Reports only carry the IDs of the reported content in the stored views
+    let mut db_conn = db_pool.get().await?;
+    match kind {
+        ReportKind::Post => {
+            let reports = db::get_known_post_reports(&mut db_conn, domain, vec![report_id]).await?;
+            let report: PostReportView = stored_view(reports.into_iter().next().map(|r| r.data))?;
+            match action {
+                ModerationAction::Lock => lemmy::lock_post(client, report.post.id).await,
+                _ => lemmy::remove_post(client, report.post.id, report.reason()).await,
+            }
+        }
+        ReportKind::Comment => {
+            let reports = db::get_known_comment_reports(&mut db_conn, domain, vec![report_id]).await?;
+            let report: CommentReportView = stored_view(reports.into_iter().next().map(|r| r.data))?;
+            match action {
+                ModerationAction::Lock => lemmy::lock_post(client, report.post.id).await,
+                _ => lemmy::remove_comment(client, report.comment.id, report.reason()).await,
+            }
+        }
+        ReportKind::PrivateMessage => Err(anyhow!("Private messages can't be removed or locked")),
+    }
+}
+
+fn stored_view<T: DeserializeOwned>(data: Option<serde_json::Value>) -> anyhow::Result<T> {
+    let data = data.ok_or_else(|| anyhow!("Report is unknown"))?;
+    Ok(serde_json::from_value(data)?)
+}
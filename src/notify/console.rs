@@ -1,11 +1,18 @@
 use async_trait::async_trait;
-use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView};
-use crate::notify::NotifyReport;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use crate::env::CONSOLE_NAME;
+use crate::models::federation_gap::FederationGap;
+use crate::notify::{describe_federation_gap, NotifyReport};
+use crate::report::{ReportKind, ReportUpdate};
 
 pub struct ConsoleNotifyReport();
 
 #[async_trait]
 impl NotifyReport for ConsoleNotifyReport {
+    fn name(&self) -> &str {
+        CONSOLE_NAME
+    }
+
     async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()> {
         println!("New post report from {source_domain}: {report:?}", report = report.post_report);
         Ok(())
@@ -15,4 +22,32 @@ impl NotifyReport for ConsoleNotifyReport {
         println!("New comment report from {source_domain}: {report:?}", report = report.comment_report);
         Ok(())
     }
+
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()> {
+        println!("New private message report from {source_domain}: {report:?}", report = report.private_message_report);
+        Ok(())
+    }
+
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()> {
+        println!("New registration application from {source_domain}: {application:?}", application = application.registration_application);
+        Ok(())
+    }
+
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()> {
+        println!("{kind} report {id} from {source_domain}: {state}", kind = update.kind, id = update.report_id, state = update.state());
+        Ok(())
+    }
+
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()> {
+        println!("Federation gaps:");
+        for gap in gaps {
+            println!("  {}", describe_federation_gap(gap));
+        }
+        Ok(())
+    }
+
+    async fn notify_copy(&self, origin_domain: &str, kind: ReportKind, origin_report_id: i32, seen_on: &[String]) -> anyhow::Result<()> {
+        println!("{kind} report {origin_report_id} from {origin_domain} was also seen on {seen_on}", seen_on = seen_on.join(", "));
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -1,71 +1,400 @@
+use crate::db;
+use crate::db::DbPool;
+use crate::env::DiscordEnvVariables;
+use crate::format::{discord, truncate_utf16, utf16_len};
+use crate::models::discord_message::DiscordMessageEntity;
+use crate::models::federation_gap::FederationGap;
+use crate::notify::NotifyReport;
+use crate::report::{ReportKind, ReportUpdate, ReportView};
+use crate::templates::{federation_gaps_context, registration_application_context, report_context, update_context, Templates};
 use anyhow::anyhow;
 use async_trait::async_trait;
-use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView};
-use webhook::client::WebhookClient;
-use crate::notify::NotifyReport;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use reqwest::Url;
+use minijinja::Value;
+use serde::{Deserialize, Serialize};
+
+const RESOLVED_COLOR: u32 = 0x2ECC71;
+
+/// Every `fields` template renders one `Name: value` line per field, lines without a name continue the previous value.
+/// Inserted values are kept on one line, so they can't add fields.
+pub const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("username", "Report Notifier"),
+    ("post.title", "{{ report.post.name }}"),
+    ("post.description", "{{ report.post.body if report.post.body else 'Empty' }}"),
+    ("post.fields", "Post: {{ url }}\nReport reason: {{ report.post_report.reason }}\nPost Author: {{ report.post_creator.actor_id }}\nReports page: {{ reports_url }}"),
+    ("comment.title", "Comment on post"),
+    ("comment.description", "{{ report.comment.content }}"),
+    ("comment.fields", "Comment: {{ url }}\nReport reason: {{ report.comment_report.reason }}\nComment Author: {{ report.comment_creator.actor_id }}\nReports page: {{ reports_url }}"),
+    ("private_message.title", "Private message"),
+    ("private_message.description", "{{ report.private_message.content }}"),
+    ("private_message.fields", "Report reason: {{ report.private_message_report.reason }}\nMessage Author: {{ report.private_message_creator.actor_id }}\nReports page: {{ reports_url }}"),
+    ("registration_application.title", "Registration application"),
+    ("registration_application.description", "{{ application.registration_application.answer }}"),
+    ("registration_application.fields", "Applicant: {{ application.creator.actor_id }}\nEmail verified: {{ 'Yes' if email_verified else 'No' }}\nApplications page: {{ applications_url }}"),
+    ("update.title", "{{ update.kind }} report {{ state }}"),
+    ("update.description", "{{ update.summary }}"),
+    ("update.fields", "{% if update.url %}Content: {{ update.url }}\n{% endif %}Reports page: {{ reports_url }}"),
+    ("federation_gaps.title", "Federation gaps"),
+    ("federation_gaps.description", "{{ summary }}"),
+    ("status_label", "Status"),
+    ("status", "{% if update.resolved %}Resolved{% if update.resolver %} by {{ update.resolver }}{% endif %}{% elif update.content_removed %}Content removed{% else %}Reopened{% endif %}"),
+    ("seen_on_label", "Seen on"),
+];
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct DiscordMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default)]
+    embeds: Vec<DiscordEmbed>,
+}
 
-const USERNAME: &str = "Report Notifier";
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct DiscordEmbed {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<u32>,
+    #[serde(default)]
+    fields: Vec<DiscordEmbedField>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct DiscordEmbedField {
+    name: String,
+    value: String,
+    #[serde(default)]
+    inline: bool,
+}
+
+#[derive(Deserialize)]
+struct DiscordMessageResponse {
+    id: String,
+}
+
+impl DiscordMessage {
+    fn new(username: String, embed: DiscordEmbed) -> Self {
+        DiscordMessage {
+            username: Some(username),
+            embeds: vec![embed],
+        }
+    }
+
+    /// Shortens the message to Discord's limits, which would otherwise reject the whole message
+    fn fit(mut self) -> Self {
+        if let Some(username) = &mut self.username {
+            shorten(username, discord::USERNAME_LIMIT);
+        }
+        let mut remaining = discord::TOTAL_LIMIT;
+        for embed in &mut self.embeds {
+            embed.fit(remaining);
+            remaining = remaining.saturating_sub(embed.text_len());
+        }
+        self
+    }
+}
+
+fn shorten(text: &mut String, max: usize) {
+    *text = truncate_utf16(text, max).into_owned();
+}
+
+impl DiscordEmbed {
+    fn new(title: &str, description: &str) -> Self {
+        DiscordEmbed {
+            title: Some(title.to_string()),
+            description: Some(description.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn field(mut self, name: &str, value: &str) -> Self {
+        self.fields.push(DiscordEmbedField {
+            name: name.to_string(),
+            value: value.to_string(),
+            inline: false,
+        });
+        self
+    }
+
+    /// Adds the fields rendered by a `fields` template
+    fn rendered_fields(mut self, rendered: &str) -> Self {
+        for line in rendered.lines() {
+            match line.split_once(": ") {
+                Some((name, value)) if !name.trim().is_empty() => self = self.field(name.trim(), value),
+                _ => match self.fields.last_mut() {
+                    Some(field) => {
+                        field.value.push('\n');
+                        field.value.push_str(line);
+                    }
+                    None => continue,
+                },
+            }
+        }
+        // Discord rejects fields without a value
+        self.fields.retain(|field| !field.value.trim().is_empty());
+        self
+    }
+
+    fn text_len(&self) -> usize {
+        self.title.as_deref().map_or(0, utf16_len)
+            + self.description.as_deref().map_or(0, utf16_len)
+            + self.fields.iter().map(|field| utf16_len(&field.name) + utf16_len(&field.value)).sum::<usize>()
+    }
+
+    /// Applies the limits of every part, then takes what's still missing to stay within `total_limit` from the description and the last fields
+    fn fit(&mut self, total_limit: usize) {
+        if let Some(title) = &mut self.title {
+            shorten(title, discord::TITLE_LIMIT);
+        }
+        if let Some(description) = &mut self.description {
+            shorten(description, discord::DESCRIPTION_LIMIT);
+        }
+        self.fields.truncate(discord::FIELDS_LIMIT);
+        for field in &mut self.fields {
+            shorten(&mut field.name, discord::FIELD_NAME_LIMIT);
+            shorten(&mut field.value, discord::FIELD_VALUE_LIMIT);
+        }
+        let excess = self.text_len().saturating_sub(total_limit);
+        if let Some(description) = self.description.as_mut().filter(|_| excess > 0) {
+            let max = utf16_len(description).saturating_sub(excess);
+            shorten(description, max);
+        }
+        while self.text_len() > total_limit && self.fields.pop().is_some() {}
+    }
+
+    /// Marks the embed as handled or restores it when the report was reopened
+    fn apply_update(&mut self, update: &ReportUpdate, status_label: &str, status: &str) {
+        let title = self.title.as_deref().unwrap_or_default();
+        let title = title.strip_prefix("~~").and_then(|t| t.strip_suffix("~~")).unwrap_or(title);
+        // Leaves room for the strikethrough, so truncating the title later can't cut it off
+        let title = truncate_utf16(title, discord::TITLE_LIMIT - 4).into_owned();
+        let handled = update.resolved || update.content_removed;
+        self.title = match handled {
+            true => Some(format!("~~{title}~~")),
+            false => Some(title),
+        };
+        self.color = handled.then_some(RESOLVED_COLOR);
+
+        self.fields.retain(|field| field.name != status_label);
+        self.fields.insert(0, DiscordEmbedField {
+            name: status_label.to_string(),
+            value: status.to_string(),
+            inline: false,
+        });
+    }
+
+    fn set_seen_on(&mut self, seen_on_label: &str, seen_on: &[String]) {
+        let value = seen_on.join(", ");
+        match self.fields.iter_mut().find(|field| field.name == seen_on_label) {
+            Some(field) => field.value = value,
+            None => self.fields.push(DiscordEmbedField {
+                name: seen_on_label.to_string(),
+                value,
+                inline: false,
+            }),
+        }
+    }
+}
+
+pub struct DiscordNotifier {
+    name: String,
+    templates: Templates,
+    http: reqwest::Client,
+    webhook_url: String,
+    webhook_id: String,
+    /// Used to remember which message belongs to which report, so it can be edited later
+    db_pool: DbPool,
+}
+
+impl DiscordNotifier {
+    pub fn new(vars: &DiscordEnvVariables, db_pool: DbPool) -> anyhow::Result<Self> {
+        Ok(DiscordNotifier {
+            name: vars.name.clone(),
+            templates: Templates::new(DEFAULT_TEMPLATES, &vars.templates)?.with_markdown_escaping(".fields"),
+            http: reqwest::Client::new(),
+            webhook_url: vars.webhook.url().to_string(),
+            webhook_id: vars.webhook.id().to_string(),
+            db_pool,
+        })
+    }
+
+    /// Renders the `title`, `description` and `fields` templates of the event
+    fn render_message(&self, event: &str, context: &Value) -> anyhow::Result<DiscordMessage> {
+        let title = self.templates.render(&format!("{event}.title"), context)?;
+        let description = self.templates.render(&format!("{event}.description"), context)?;
+        let mut embed = DiscordEmbed::new(&title, &description);
+        let fields_template = format!("{event}.fields");
+        if self.templates.contains(&fields_template) {
+            embed = embed.rendered_fields(&self.templates.render(&fields_template, context)?);
+        }
+        Ok(DiscordMessage::new(self.templates.render("username", context)?, embed))
+    }
+
+    fn message_url(&self, message_id: &str) -> anyhow::Result<Url> {
+        let mut url = Url::parse(&self.webhook_url)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Discord webhook url can't be a base"))?
+            .push("messages")
+            .push(message_id);
+        Ok(url)
+    }
+
+    /// Sends the message and waits for Discord to return the ID of the created message
+    async fn send(&self, message: &DiscordMessage) -> anyhow::Result<String> {
+        let response = self.http
+            .post(&self.webhook_url)
+            .query(&[("wait", "true")])
+            .json(&message.clone().fit())
+            .send()
+            .await?
+            .error_for_status()?;
+        let created: DiscordMessageResponse = response.json().await?;
+        Ok(created.id)
+    }
+
+    async fn get(&self, message_id: &str) -> anyhow::Result<DiscordMessage> {
+        let response = self.http
+            .get(self.message_url(message_id)?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    async fn edit(&self, message_id: &str, message: &DiscordMessage) -> anyhow::Result<()> {
+        self.http
+            .patch(self.message_url(message_id)?)
+            .json(&message.clone().fit())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn send_report(&self, domain: &str, kind: ReportKind, report_id: i32, message: &DiscordMessage) -> anyhow::Result<()> {
+        let message_id = self.send(message).await?;
+        let entity = DiscordMessageEntity {
+            domain: domain.to_string(),
+            kind: kind.to_string(),
+            report_id,
+            webhook_id: self.webhook_id.clone(),
+            message_id,
+        };
+        let mut db_conn = self.db_pool.get().await?;
+        db::insert_discord_message(&mut db_conn, &entity).await
+    }
+}
 
 #[async_trait]
-impl NotifyReport for WebhookClient {
+impl NotifyReport for DiscordNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()> {
-        send_post_report_notification(self, source_domain, report).await
+        let message = self.render_message("post", &report_context(source_domain, report))?;
+        self.send_report(source_domain, ReportKind::Post, report.report_id(), &message).await
     }
 
     async fn notify_comment(&self, source_domain: &str, report: &CommentReportView) -> anyhow::Result<()> {
-        send_comment_report_notification(self, source_domain, report).await
+        let message = self.render_message("comment", &report_context(source_domain, report))?;
+        self.send_report(source_domain, ReportKind::Comment, report.report_id(), &message).await
+    }
+
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()> {
+        let message = self.render_message("private_message", &report_context(source_domain, report))?;
+        self.send_report(source_domain, ReportKind::PrivateMessage, report.report_id(), &message).await
+    }
+
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()> {
+        let message = self.render_message("registration_application", &registration_application_context(source_domain, application))?;
+        self.send(&message).await?;
+        Ok(())
+    }
+
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()> {
+        let message_id = {
+            let mut db_conn = self.db_pool.get().await?;
+            db::get_discord_message_id(&mut db_conn, source_domain, update.kind, update.report_id, &self.webhook_id).await?
+        };
+        let context = update_context(source_domain, update);
+        let status_label = self.templates.render("status_label", &context)?;
+        let status = self.templates.render("status", &context)?;
+        match message_id {
+            Some(message_id) => {
+                let mut message = self.get(&message_id).await?;
+                message.username = None;
+                for embed in &mut message.embeds {
+                    embed.apply_update(update, &status_label, &status);
+                }
+                self.edit(&message_id, &message).await
+            }
+            // Reports sent before message IDs were stored can't be edited
+            None => {
+                let mut message = self.render_message("update", &context)?;
+                for embed in &mut message.embeds {
+                    embed.apply_update(update, &status_label, &status);
+                }
+                self.send(&message).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()> {
+        self.send(&self.render_message("federation_gaps", &federation_gaps_context(gaps))?).await?;
+        Ok(())
     }
-}
 
-pub async fn send_post_report_notification(client: &WebhookClient, domain: &str, report: &PostReportView) -> anyhow::Result<()> {
-    let view_url = format!("https://{domain}/reports");
-    let post_id = &report.post.id;
-    let post_url = format!("https://{domain}/post/{post_id}");
-    let post_title = report.post.name.to_string();
-    let post_description = match &report.post.body {
-        Some(title) => {
-            title.to_string()
+    async fn notify_copy(&self, origin_domain: &str, kind: ReportKind, origin_report_id: i32, seen_on: &[String]) -> anyhow::Result<()> {
+        let message_id = {
+            let mut db_conn = self.db_pool.get().await?;
+            db::get_discord_message_id(&mut db_conn, origin_domain, kind, origin_report_id, &self.webhook_id).await?
+        };
+        if let Some(message_id) = message_id {
+            let seen_on_label = self.templates.render("seen_on_label", &Value::UNDEFINED)?;
+            let mut message = self.get(&message_id).await?;
+            message.username = None;
+            for embed in &mut message.embeds {
+                embed.set_seen_on(&seen_on_label, seen_on);
+            }
+            self.edit(&message_id, &message).await?;
         }
-        _ => { "Empty".to_string() }
-    };
-    let report_reason = report.post_report.reason.to_string();
-    let user = report.post_creator.actor_id.to_string();
-    client.send(|message| message
-        .username(USERNAME)
-        .embed(|embed| embed
-            .title(&post_title)
-            .description(&post_description)
-            .field("Post", &post_url, false)
-            .field("Report reason", &report_reason, false)
-            .field("Post Author", &user, false)
-            .field("Reports page", &view_url, false)
-        ))
-        .await
-        .map_err(|e| anyhow!(e))?;
-
-    Ok(())
+        Ok(())
+    }
 }
 
-pub async fn send_comment_report_notification(client: &WebhookClient, domain: &str, report: &CommentReportView) -> anyhow::Result<()> {
-    let view_url = format!("https://{domain}/reports");
-    let post_id = report.post.id;
-    let comment_id = report.comment.id;
-    let comment_url = format!("https://{domain}/post/{post_id}/{comment_id}");
-    let comment = &report.comment.content.to_string();
-    let report_reason = report.comment_report.reason.to_string();
-    let user = report.comment_creator.actor_id.to_string();
-    client.send(|message| message
-        .username(USERNAME)
-        .embed(|embed| embed
-            .title("Comment on post")
-            .description(comment)
-            .field("Comment", &comment_url, false)
-            .field("Report reason", &report_reason, false)
-            .field("Comment Author", &user, false)
-            .field("Reports page", &view_url, false)
-        ))
-        .await
-        .map_err(|e| anyhow!(e))?;
-
-    Ok(())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_long_post() {
+        let embed = DiscordEmbed::new(&"t".repeat(300), &"b".repeat(5000))
+            .field("Report reason", &"r".repeat(2000))
+            .field("Reports page", "https://lemmy.world/reports");
+        let message = DiscordMessage::new("Report Notifier".to_string(), embed).fit();
+        let embed = &message.embeds[0];
+        assert_eq!(discord::TITLE_LIMIT, utf16_len(embed.title.as_deref().unwrap()));
+        assert_eq!(discord::DESCRIPTION_LIMIT, utf16_len(embed.description.as_deref().unwrap()));
+        assert_eq!(discord::FIELD_VALUE_LIMIT, utf16_len(&embed.fields[0].value));
+        assert_eq!(2, embed.fields.len());
+        assert!(embed.text_len() <= discord::TOTAL_LIMIT);
+    }
+
+    #[test]
+    fn fit_total_from_description() {
+        let mut embed = DiscordEmbed::new("Title", &"b".repeat(4000));
+        for i in 0..3 {
+            embed = embed.field(&format!("Field {i}"), &"v".repeat(1000));
+        }
+        let message = DiscordMessage::new("Report Notifier".to_string(), embed).fit();
+        let embed = &message.embeds[0];
+        assert_eq!(discord::TOTAL_LIMIT, embed.text_len());
+        assert_eq!(3, embed.fields.len());
+        assert!(embed.description.as_deref().unwrap().ends_with(crate::format::TRUNCATION_MARKER));
+    }
+}
@@ -0,0 +1,247 @@
+use crate::config::SmtpSecurity;
+use crate::db;
+use crate::db::DbPool;
+use crate::env::EmailEnvVariables;
+use crate::models::email_digest_entry::{EmailDigestEntryEntity, NewEmailDigestEntryEntity};
+use crate::models::federation_gap::FederationGap;
+use crate::notify::NotifyReport;
+use crate::report::ReportUpdate;
+use crate::templates::{federation_gaps_context, registration_application_context, report_context, update_context, Templates};
+use crate::util::sleep;
+use async_trait::async_trait;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use minijinja::{context, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Every event has a `subject`, a plain text `text` and an `html` template, values inserted into the latter are HTML escaped.
+/// The `digest` templates combine the rendered notifications of an interval.
+pub const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("post.subject", "Post report on {{ source_domain }}: {{ report.post.name|truncate(100) }}"),
+    ("post.text", "{{ report.post.name }}{% if report.post.body %}\n\n{{ report.post.body }}{% endif %}\n\nReason: {{ report.post_report.reason }}\nAuthor: {{ report.post_creator.actor_id }}\n\nPost: {{ url }}\nReports: {{ reports_url }}"),
+    ("post.html", "<h3>Post report on {{ source_domain }}</h3><p><a href=\"{{ url }}\">{{ report.post.name }}</a></p>{% if report.post.body %}<blockquote>{{ report.post.body }}</blockquote>{% endif %}<p><b>Reason:</b> {{ report.post_report.reason }}<br><b>Author:</b> {{ report.post_creator.actor_id }}</p><p><a href=\"{{ reports_url }}\">Reports</a></p>"),
+    ("comment.subject", "Comment report on {{ source_domain }}: {{ report.post.name|truncate(100) }}"),
+    ("comment.text", "{{ report.comment.content }}\n\nReason: {{ report.comment_report.reason }}\nAuthor: {{ report.comment_creator.actor_id }}\nPost: {{ report.post.name }}\n\nComment: {{ url }}\nReports: {{ reports_url }}"),
+    ("comment.html", "<h3>Comment report on {{ source_domain }}</h3><p>In <a href=\"{{ report.post.ap_id }}\">{{ report.post.name }}</a></p><blockquote>{{ report.comment.content }}</blockquote><p><b>Reason:</b> {{ report.comment_report.reason }}<br><b>Author:</b> {{ report.comment_creator.actor_id }}</p><p><a href=\"{{ url }}\">Comment</a> · <a href=\"{{ reports_url }}\">Reports</a></p>"),
+    ("private_message.subject", "Private message report on {{ source_domain }}"),
+    ("private_message.text", "{{ report.private_message.content }}\n\nReason: {{ report.private_message_report.reason }}\nAuthor: {{ report.private_message_creator.actor_id }}\n\nReports: {{ reports_url }}"),
+    ("private_message.html", "<h3>Private message report on {{ source_domain }}</h3><blockquote>{{ report.private_message.content }}</blockquote><p><b>Reason:</b> {{ report.private_message_report.reason }}<br><b>Author:</b> {{ report.private_message_creator.actor_id }}</p><p><a href=\"{{ reports_url }}\">Reports</a></p>"),
+    ("registration_application.subject", "Registration application on {{ source_domain }}: {{ application.creator.name }}"),
+    ("registration_application.text", "{{ application.creator.name }} applied to join {{ source_domain }}\n\n{{ application.registration_application.answer }}\n\nEmail {{ 'verified' if email_verified else 'unverified' }}\nApplications: {{ applications_url }}"),
+    ("registration_application.html", "<h3>Registration application on {{ source_domain }}</h3><p>{{ application.creator.name }}</p><blockquote>{{ application.registration_application.answer }}</blockquote><p>Email {{ 'verified' if email_verified else 'unverified' }}</p><p><a href=\"{{ applications_url }}\">Applications</a></p>"),
+    ("update.subject", "{{ update.kind }} report {{ state }} on {{ source_domain }}"),
+    ("update.text", "{{ update.kind }} report {{ state }}: {{ update.summary }}{% if update.resolver %} (by {{ update.resolver }}){% endif %}{% if update.url %}\n{{ update.url }}{% endif %}"),
+    ("update.html", "<p><b>{{ update.kind }} report {{ state }}</b>: {% if update.url %}<a href=\"{{ update.url }}\">{{ update.summary }}</a>{% else %}{{ update.summary }}{% endif %}{% if update.resolver %} (by {{ update.resolver }}){% endif %}</p>"),
+    ("federation_gaps.subject", "Federation gaps"),
+    ("federation_gaps.text", "{{ summary }}"),
+    ("federation_gaps.html", "<h3>Federation gaps</h3><pre>{{ summary }}</pre>"),
+    ("digest.subject", "{{ entries|length }} new notification{{ 's' if entries|length != 1 }} from Lemmy Know"),
+    ("digest.text", "{% for entry in entries %}{{ entry.subject }}\n\n{{ entry.text }}\n{% if not loop.last %}\n----------\n\n{% endif %}{% endfor %}"),
+    ("digest.html", "{% for entry in entries %}{{ entry.html|safe }}{% if not loop.last %}<hr>{% endif %}{% endfor %}"),
+];
+
+struct RenderedEmail {
+    subject: String,
+    text: String,
+    html: String,
+}
+
+struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Mailer {
+    fn new(vars: &EmailEnvVariables) -> anyhow::Result<Self> {
+        let mut builder = match vars.security {
+            SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&vars.host)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&vars.host)?,
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&vars.host),
+        }.port(vars.port);
+        if let Some(creds) = &vars.credentials {
+            builder = builder.credentials(Credentials::new(creds.username.clone(), creds.password.clone()));
+        }
+        Ok(Mailer { transport: builder.build(), from: vars.from.clone(), to: vars.to.clone() })
+    }
+
+    async fn send(&self, email: RenderedEmail) -> anyhow::Result<()> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            // Line breaks would end the header
+            .subject(email.subject.replace(['\r', '\n'], " "));
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        let message = builder.multipart(MultiPart::alternative_plain_html(email.text, email.html))?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+pub struct EmailNotifier {
+    name: String,
+    templates: Templates,
+    mailer: Arc<Mailer>,
+    /// Notifications are collected for the next digest instead of being sent right away
+    digest: bool,
+    db_pool: DbPool,
+}
+
+impl EmailNotifier {
+    /// In digest mode, also starts sending the collected notifications every interval until the token is cancelled
+    pub fn new(vars: &EmailEnvVariables, db_pool: DbPool, cancellation_token: CancellationToken) -> anyhow::Result<Self> {
+        let mailer = Arc::new(Mailer::new(vars)?);
+        let templates = Templates::new(DEFAULT_TEMPLATES, &vars.templates)?;
+        if let Some(interval) = vars.digest_interval {
+            let digest = DigestSender {
+                name: vars.name.clone(),
+                templates: Templates::new(DEFAULT_TEMPLATES, &vars.templates)?,
+                mailer: mailer.clone(),
+                db_pool: db_pool.clone(),
+            };
+            tokio::spawn(digest.run(Duration::from_secs(interval), cancellation_token));
+        }
+        Ok(EmailNotifier {
+            name: vars.name.clone(),
+            templates,
+            mailer,
+            digest: vars.digest_interval.is_some(),
+            db_pool,
+        })
+    }
+
+    fn render(&self, event: &str, context: &Value) -> anyhow::Result<RenderedEmail> {
+        Ok(RenderedEmail {
+            subject: self.templates.render(&format!("{event}.subject"), context)?,
+            text: self.templates.render(&format!("{event}.text"), context)?,
+            html: self.templates.render(&format!("{event}.html"), context)?,
+        })
+    }
+
+    async fn send(&self, event: &str, context: &Value) -> anyhow::Result<()> {
+        let email = self.render(event, context)?;
+        if !self.digest {
+            return self.mailer.send(email).await;
+        }
+        let entry = NewEmailDigestEntryEntity {
+            notifier: self.name.clone(),
+            subject: email.subject,
+            text: email.text,
+            html: email.html,
+        };
+        let mut db_conn = self.db_pool.get().await?;
+        db::insert_email_digest_entry(&mut db_conn, &entry).await
+    }
+}
+
+struct DigestSender {
+    name: String,
+    templates: Templates,
+    mailer: Arc<Mailer>,
+    db_pool: DbPool,
+}
+
+impl DigestSender {
+    async fn run(self, interval: Duration, token: CancellationToken) {
+        sleep(interval, &token).await;
+        while !token.is_cancelled() {
+            if let Err(e) = self.send_digest().await {
+                eprintln!("Failed to send the digest of email notifier '{}': {e}", self.name);
+            }
+            sleep(interval, &token).await;
+        }
+    }
+
+    /// Entries are only removed once the digest was sent, so they're retried with the next one otherwise
+    async fn send_digest(&self) -> anyhow::Result<()> {
+        let mut db_conn = self.db_pool.get().await?;
+        let entries = db::get_email_digest_entries(&mut db_conn, &self.name).await?;
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let email = render_digest(&self.templates, &entries)?;
+        self.mailer.send(email).await?;
+        let ids = entries.iter().map(|entry| entry.id).collect();
+        db::delete_email_digest_entries(&mut db_conn, ids).await?;
+        println!("Sent a digest of {} notifications with email notifier '{}'", entries.len(), self.name);
+        Ok(())
+    }
+}
+
+fn render_digest(templates: &Templates, entries: &[EmailDigestEntryEntity]) -> anyhow::Result<RenderedEmail> {
+    let entries = entries
+        .iter()
+        .map(|entry| context! { subject => entry.subject, text => entry.text, html => entry.html })
+        .collect::<Vec<_>>();
+    let context = context! { entries };
+    Ok(RenderedEmail {
+        subject: templates.render("digest.subject", &context)?,
+        text: templates.render("digest.text", &context)?,
+        html: templates.render("digest.html", &context)?,
+    })
+}
+
+#[async_trait]
+impl NotifyReport for EmailNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()> {
+        self.send("post", &report_context(source_domain, report)).await
+    }
+
+    async fn notify_comment(&self, source_domain: &str, report: &CommentReportView) -> anyhow::Result<()> {
+        self.send("comment", &report_context(source_domain, report)).await
+    }
+
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()> {
+        self.send("private_message", &report_context(source_domain, report)).await
+    }
+
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()> {
+        self.send("registration_application", &registration_application_context(source_domain, application)).await
+    }
+
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()> {
+        self.send("update", &update_context(source_domain, update)).await
+    }
+
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()> {
+        self.send("federation_gaps", &federation_gaps_context(gaps)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn entry(id: i64, subject: &str, html: &str) -> EmailDigestEntryEntity {
+        EmailDigestEntryEntity {
+            id,
+            notifier: "mods".to_string(),
+            subject: subject.to_string(),
+            text: subject.to_lowercase(),
+            html: html.to_string(),
+        }
+    }
+
+    #[test]
+    fn digest_keeps_rendered_html() {
+        let templates = Templates::new(DEFAULT_TEMPLATES, &HashMap::new()).unwrap();
+        let entries = [
+            entry(1, "Post report on lemmy.world", "<h3>Post &amp; report</h3>"),
+            entry(2, "Comment report on lemmy.world", "<h3>Comment report</h3>"),
+        ];
+        let digest = render_digest(&templates, &entries).unwrap();
+        assert_eq!("2 new notifications from Lemmy Know", digest.subject);
+        assert_eq!("<h3>Post &amp; report</h3><hr><h3>Comment report</h3>", digest.html);
+        assert!(digest.text.starts_with("Post report on lemmy.world\n\npost report on lemmy.world\n\n----------"));
+    }
+}
@@ -0,0 +1,143 @@
+use serde_json::{json, Value};
+
+/// Entities of every instance device as `(component, key)`
+const ENTITIES: &[(&str, &str)] = &[
+    ("sensor", "open_post_reports"),
+    ("sensor", "open_comment_reports"),
+    ("sensor", "open_reports"),
+    ("sensor", "last_poll"),
+    ("binary_sensor", "login"),
+    ("event", "report"),
+];
+
+/// Events of the `report` event entity
+pub const EVENT_TYPES: &[&str] = &["post", "comment", "private_message", "registration_application"];
+
+/// Builds the [MQTT discovery](https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery) configs of Home Assistant,
+/// with one device per instance
+pub struct Discovery {
+    pub prefix: String,
+    /// Keeps the entities of lemmy-know instances on the same broker apart
+    pub node_id: String,
+    pub availability_topic: String,
+}
+
+/// Topic of a single entity, e.g. `homeassistant/sensor/lemmy-know/lemmy_world_open_reports/config`
+fn config_topic(prefix: &str, component: &str, node_id: &str, object_id: &str) -> String {
+    format!("{prefix}/{component}/{node_id}/{object_id}/config")
+}
+
+/// Home Assistant only allows letters, digits, `_` and `-` in IDs
+pub fn slug(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+impl Discovery {
+    /// Retained configs that add the device of the instance
+    pub fn configs(&self, domain: &str, topics: &InstanceTopics) -> Vec<(String, Value)> {
+        let device = json!({
+            "identifiers": [format!("{}_{}", self.node_id, slug(domain))],
+            "name": domain,
+            "manufacturer": "Lemmy Know",
+            "model": "Lemmy instance",
+            "configuration_url": format!("https://{domain}"),
+        });
+        ENTITIES
+            .iter()
+            .map(|(component, key)| {
+                let object_id = format!("{}_{key}", slug(domain));
+                let mut config = entity_config(key, topics);
+                config["unique_id"] = json!(format!("{}_{object_id}", self.node_id));
+                config["availability_topic"] = json!(self.availability_topic);
+                config["device"] = device.clone();
+                (config_topic(&self.prefix, component, &self.node_id, &object_id), config)
+            })
+            .collect()
+    }
+
+    /// Topics to clear to remove the device of an instance that isn't monitored anymore
+    pub fn config_topics(&self, domain: &str) -> Vec<String> {
+        ENTITIES
+            .iter()
+            .map(|(component, key)| config_topic(&self.prefix, component, &self.node_id, &format!("{}_{key}", slug(domain))))
+            .collect()
+    }
+}
+
+/// State topics of one instance
+pub struct InstanceTopics {
+    pub open_reports: String,
+    pub status: String,
+    pub events: String,
+}
+
+fn entity_config(key: &str, topics: &InstanceTopics) -> Value {
+    match key {
+        "open_post_reports" => open_reports_sensor("Open post reports", "posts", topics),
+        "open_comment_reports" => open_reports_sensor("Open comment reports", "comments", topics),
+        "open_reports" => open_reports_sensor("Open reports", "total", topics),
+        "last_poll" => json!({
+            "name": "Last poll",
+            "device_class": "timestamp",
+            "state_topic": topics.status,
+            "value_template": "{{ value_json.last_poll }}",
+            "entity_category": "diagnostic",
+        }),
+        "login" => json!({
+            "name": "Login",
+            "device_class": "problem",
+            "state_topic": topics.status,
+            "value_template": "{{ 'OFF' if value_json.logged_in else 'ON' }}",
+            "json_attributes_topic": topics.status,
+            "entity_category": "diagnostic",
+        }),
+        _ => json!({
+            "name": "Report",
+            "state_topic": topics.events,
+            "event_types": EVENT_TYPES,
+            "icon": "mdi:flag",
+        }),
+    }
+}
+
+fn open_reports_sensor(name: &str, field: &str, topics: &InstanceTopics) -> Value {
+    json!({
+        "name": name,
+        "state_topic": topics.open_reports,
+        "value_template": format!("{{{{ value_json.{field} }}}}"),
+        "state_class": "measurement",
+        "unit_of_measurement": "reports",
+        "icon": "mdi:flag",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_of_instance() {
+        let discovery = Discovery {
+            prefix: "homeassistant".to_string(),
+            node_id: "lemmy-know".to_string(),
+            availability_topic: "lemmy-know/availability".to_string(),
+        };
+        let topics = InstanceTopics {
+            open_reports: "lemmy-know/open_reports/lemmy.world".to_string(),
+            status: "lemmy-know/status/lemmy.world".to_string(),
+            events: "lemmy-know/report_events/lemmy.world".to_string(),
+        };
+        let configs = discovery.configs("lemmy.world", &topics);
+        assert_eq!(ENTITIES.len(), configs.len());
+
+        let (topic, config) = &configs[2];
+        assert_eq!("homeassistant/sensor/lemmy-know/lemmy_world_open_reports/config", topic);
+        assert_eq!("lemmy-know_lemmy_world_open_reports", config["unique_id"]);
+        assert_eq!("{{ value_json.total }}", config["value_template"]);
+        assert_eq!("lemmy.world", config["device"]["name"]);
+        assert_eq!(discovery.config_topics("lemmy.world")[2], *topic);
+    }
+}
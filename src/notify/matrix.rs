@@ -0,0 +1,337 @@
+use crate::crypto;
+use crate::db;
+use crate::db::DbPool;
+use crate::env::MatrixEnvVariables;
+use crate::format::{truncate_bytes, MATRIX_BODY_LIMIT};
+use crate::models::federation_gap::FederationGap;
+use crate::models::matrix_message::MatrixMessageEntity;
+use crate::notify::NotifyReport;
+use crate::report::{ReportUpdate, ReportView};
+use crate::stupid;
+use crate::templates::{federation_gaps_context, registration_application_context, report_context, update_context, Templates};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use minijinja::Value;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// Every event has a plain text `text` template and an `html` template, values inserted into the latter are HTML escaped
+pub const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("post.text", "Post report on {{ source_domain }}: {{ report.post.name }}{% if report.post.body %}\n{{ report.post.body }}{% endif %}\nReason: {{ report.post_report.reason }}\nAuthor: {{ report.post_creator.actor_id }}\n{{ url }}"),
+    ("post.html", "<b>Post report on {{ source_domain }}</b>: <a href=\"{{ url }}\">{{ report.post.name }}</a>{% if report.post.body %}<blockquote>{{ report.post.body }}</blockquote>{% else %}<br>{% endif %}<b>Reason:</b> {{ report.post_report.reason }}<br><b>Author:</b> {{ report.post_creator.actor_id }}<br><a href=\"{{ reports_url }}\">Reports</a>"),
+    ("comment.text", "Comment report on {{ source_domain }}\n{{ report.comment.content }}\nReason: {{ report.comment_report.reason }}\nAuthor: {{ report.comment_creator.actor_id }}\n{{ url }}"),
+    ("comment.html", "<b>Comment report on {{ source_domain }}</b> in <a href=\"{{ report.post.ap_id }}\">{{ report.post.name }}</a><blockquote>{{ report.comment.content }}</blockquote><b>Reason:</b> {{ report.comment_report.reason }}<br><b>Author:</b> {{ report.comment_creator.actor_id }}<br><a href=\"{{ url }}\">Comment</a> · <a href=\"{{ reports_url }}\">Reports</a>"),
+    ("private_message.text", "Private message report on {{ source_domain }}\n{{ report.private_message.content }}\nReason: {{ report.private_message_report.reason }}\nAuthor: {{ report.private_message_creator.actor_id }}\n{{ reports_url }}"),
+    ("private_message.html", "<b>Private message report on {{ source_domain }}</b><blockquote>{{ report.private_message.content }}</blockquote><b>Reason:</b> {{ report.private_message_report.reason }}<br><b>Author:</b> {{ report.private_message_creator.actor_id }}<br><a href=\"{{ reports_url }}\">Reports</a>"),
+    ("registration_application.text", "Registration application on {{ source_domain }}: {{ application.creator.name }}\n{{ application.registration_application.answer }}\nEmail {{ 'verified' if email_verified else 'unverified' }}\n{{ applications_url }}"),
+    ("registration_application.html", "<b>Registration application on {{ source_domain }}</b>: {{ application.creator.name }}<blockquote>{{ application.registration_application.answer }}</blockquote>Email {{ 'verified' if email_verified else 'unverified' }}<br><a href=\"{{ applications_url }}\">Applications</a>"),
+    ("update.text", "{{ update.kind }} report {{ state }}: {{ update.summary }}{% if update.resolver %} (by {{ update.resolver }}){% endif %}"),
+    ("update.html", "<b>{{ update.kind }} report {{ state }}</b>: {% if update.url %}<a href=\"{{ update.url }}\">{{ update.summary }}</a>{% else %}{{ update.summary }}{% endif %}{% if update.resolver %} (by {{ update.resolver }}){% endif %}"),
+    ("federation_gaps.text", "Federation gaps\n{{ summary }}"),
+    ("federation_gaps.html", "<b>Federation gaps</b><pre>{{ summary }}</pre>"),
+];
+
+const HTML_FORMAT: &str = "org.matrix.custom.html";
+
+#[derive(Serialize)]
+struct MatrixMessage {
+    msgtype: &'static str,
+    body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formatted_body: Option<String>,
+    #[serde(rename = "m.relates_to", skip_serializing_if = "Option::is_none")]
+    relates_to: Option<MatrixRelation>,
+}
+
+#[derive(Serialize)]
+struct MatrixRelation {
+    rel_type: &'static str,
+    event_id: String,
+    /// Clients without thread support show the message as a reply to the thread root instead
+    is_falling_back: bool,
+    #[serde(rename = "m.in_reply_to")]
+    in_reply_to: MatrixReplyTo,
+}
+
+#[derive(Serialize)]
+struct MatrixReplyTo {
+    event_id: String,
+}
+
+#[derive(Deserialize)]
+struct MatrixSendResponse {
+    event_id: String,
+}
+
+impl MatrixRelation {
+    fn thread(root: String) -> Self {
+        MatrixRelation {
+            rel_type: "m.thread",
+            event_id: root.clone(),
+            is_falling_back: true,
+            in_reply_to: MatrixReplyTo { event_id: root },
+        }
+    }
+}
+
+/// `PUT /_matrix/client/v3/rooms/{room_id}/send/m.room.message/{transaction_id}` of the Client-Server API
+fn send_url(homeserver: &Url, room_id: &str, transaction_id: &str) -> anyhow::Result<Url> {
+    let mut url = homeserver.clone();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Matrix homeserver url can't be a base"))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3", "rooms", room_id, "send", "m.room.message", transaction_id]);
+    Ok(url)
+}
+
+/// Room the messages are sent to through the Client-Server API
+struct MatrixRoom {
+    http: reqwest::Client,
+    homeserver: Url,
+    access_token: String,
+    room_id: String,
+}
+
+impl MatrixRoom {
+    /// Sends the message and returns the ID of the created event
+    async fn send(&self, transaction_id: &str, message: &MatrixMessage) -> anyhow::Result<String> {
+        let response = self.http
+            .put(send_url(&self.homeserver, &self.room_id, transaction_id)?)
+            .bearer_auth(&self.access_token)
+            .json(message)
+            .send()
+            .await?
+            .error_for_status()?;
+        let sent: MatrixSendResponse = response.json().await?;
+        Ok(sent.event_id)
+    }
+}
+
+pub struct MatrixNotifier {
+    name: String,
+    templates: Templates,
+    room: MatrixRoom,
+    threads: bool,
+    /// Used to find the thread of a post
+    db_pool: DbPool,
+}
+
+impl MatrixNotifier {
+    pub fn new(vars: &MatrixEnvVariables, db_pool: DbPool) -> anyhow::Result<Self> {
+        Ok(MatrixNotifier {
+            name: vars.name.clone(),
+            templates: Templates::new(DEFAULT_TEMPLATES, &vars.templates)?,
+            room: MatrixRoom {
+                http: reqwest::Client::new(),
+                homeserver: vars.homeserver.clone(),
+                access_token: vars.access_token.clone(),
+                room_id: vars.room_id.clone(),
+            },
+            threads: vars.threads,
+            db_pool,
+        })
+    }
+
+    /// Renders the `text` and `html` templates of the event
+    fn render_message(&self, event: &str, context: &Value) -> anyhow::Result<MatrixMessage> {
+        let body = self.templates.render(&format!("{event}.text"), context)?;
+        let html = self.templates.render(&format!("{event}.html"), context)?;
+        // Cutting the HTML could break its markup, clients show the plain text instead
+        let formatted_body = (html.len() <= MATRIX_BODY_LIMIT).then_some(html);
+        Ok(MatrixMessage {
+            msgtype: "m.notice",
+            body: truncate_bytes(&body, MATRIX_BODY_LIMIT).into_owned(),
+            format: formatted_body.as_ref().map(|_| HTML_FORMAT),
+            formatted_body,
+            relates_to: None,
+        })
+    }
+
+    /// Derived from the notification, so it's the same for every attempt and the homeserver can recognize retried requests
+    fn transaction_id(&self, id: &str) -> String {
+        format!("lemmy-know-{}-{id}", self.name)
+    }
+
+    async fn send_report<T: ReportView>(&self, source_domain: &str, event: &str, report: &T) -> anyhow::Result<()> {
+        let mut message = self.render_message(event, &report_context(source_domain, report))?;
+        // Reports are only sent once, so their transaction ID can stay the same between attempts
+        let transaction_id = self.transaction_id(&format!("{source_domain}-{}-{}", T::KIND, report.report_id()));
+        if !self.threads {
+            self.room.send(&transaction_id, &message).await?;
+            return Ok(());
+        }
+
+        let post_id = report.post_id();
+        let thread_root = match post_id {
+            Some(post_id) => {
+                let mut db_conn = self.db_pool.get().await?;
+                db::get_matrix_post_thread(&mut db_conn, source_domain, post_id, &self.room.room_id).await?
+            }
+            None => None,
+        };
+        message.relates_to = thread_root.clone().map(MatrixRelation::thread);
+        let event_id = self.room.send(&transaction_id, &message).await?;
+
+        let entity = MatrixMessageEntity {
+            domain: source_domain.to_string(),
+            kind: T::KIND.to_string(),
+            report_id: report.report_id(),
+            room_id: self.room.room_id.clone(),
+            post_id,
+            event_id,
+            thread_root,
+        };
+        let mut db_conn = self.db_pool.get().await?;
+        db::insert_matrix_message(&mut db_conn, &entity).await
+    }
+}
+
+#[async_trait]
+impl NotifyReport for MatrixNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, "post", report).await
+    }
+
+    async fn notify_comment(&self, source_domain: &str, report: &CommentReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, "comment", report).await
+    }
+
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, "private_message", report).await
+    }
+
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()> {
+        let message = self.render_message("registration_application", &registration_application_context(source_domain, application))?;
+        let application_id = stupid::extract_id(application.registration_application.id);
+        self.room.send(&self.transaction_id(&format!("{source_domain}-registration_application-{application_id}")), &message).await?;
+        Ok(())
+    }
+
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()> {
+        let mut message = self.render_message("update", &update_context(source_domain, update))?;
+        if self.threads {
+            let mut db_conn = self.db_pool.get().await?;
+            let thread_root = db::get_matrix_report_thread(&mut db_conn, source_domain, update.kind, update.report_id, &self.room.room_id).await?;
+            message.relates_to = thread_root.map(MatrixRelation::thread);
+        }
+        // Updates queued before the history ID was added fall back to the state
+        let change = update.history_id.map(|id| id.to_string()).unwrap_or_else(|| update.state().replace(' ', "_"));
+        self.room.send(&self.transaction_id(&format!("{source_domain}-{}-{}-update-{change}", update.kind, update.report_id)), &message).await?;
+        Ok(())
+    }
+
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()> {
+        let message = self.render_message("federation_gaps", &federation_gaps_context(gaps))?;
+        // Summaries don't have an ID, but the same summary always has the same content
+        let hash = crypto::hash(&serde_json::to_vec(gaps)?);
+        self.room.send(&self.transaction_id(&format!("federation_gaps-{hash}")), &message).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::context;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// Stand-in homeserver that answers a single request and returns it
+    fn homeserver() -> (Url, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+
+            let response = r#"{"event_id":"$sent"}"#;
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}", response.len()).unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn send_to_homeserver() {
+        let (homeserver, server) = homeserver();
+        let room = MatrixRoom {
+            http: reqwest::Client::new(),
+            homeserver,
+            access_token: "token".to_string(),
+            room_id: "!abc:localhost".to_string(),
+        };
+        let message = MatrixMessage {
+            msgtype: "m.notice",
+            body: "Post report".to_string(),
+            format: Some(HTML_FORMAT),
+            formatted_body: Some("<b>Post report</b>".to_string()),
+            relates_to: Some(MatrixRelation::thread("$root".to_string())),
+        };
+        assert_eq!("$sent", room.send("lemmy-know-matrix-1", &message).await.unwrap());
+
+        let request = server.join().unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("PUT /_matrix/client/v3/rooms/!abc:localhost/send/m.room.message/lemmy-know-matrix-1 HTTP/1.1\r\n"));
+        assert!(head.to_ascii_lowercase().contains("\r\nauthorization: bearer token\r\n"));
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!("Post report", body["body"]);
+        assert_eq!(HTML_FORMAT, body["format"]);
+        assert_eq!("<b>Post report</b>", body["formatted_body"]);
+        assert_eq!("m.thread", body["m.relates_to"]["rel_type"]);
+        assert_eq!("$root", body["m.relates_to"]["event_id"]);
+        assert_eq!(true, body["m.relates_to"]["is_falling_back"]);
+        assert_eq!("$root", body["m.relates_to"]["m.in_reply_to"]["event_id"]);
+    }
+
+    #[test]
+    fn send_url_of_local_homeserver() {
+        let homeserver = Url::parse("http://localhost:8008/").unwrap();
+        let url = send_url(&homeserver, "!abc:localhost", "lemmy-know-matrix-1").unwrap();
+        assert_eq!("http://localhost:8008/_matrix/client/v3/rooms/!abc:localhost/send/m.room.message/lemmy-know-matrix-1", url.as_str());
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let templates = Templates::new(DEFAULT_TEMPLATES, &HashMap::new()).unwrap();
+        let context = context! {
+            source_domain => "lemmy.world",
+            url => "https://lemmy.world/post/1",
+            reports_url => "https://lemmy.world/reports",
+            report => context! {
+                post => context! { name => "<script>", body => "" },
+                post_report => context! { reason => "Spam & scam" },
+                post_creator => context! { actor_id => "https://lemmy.world/u/spammer" },
+            },
+        };
+        let html = templates.render("post.html", &context).unwrap();
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("Spam &amp; scam"));
+        let text = templates.render("post.text", &context).unwrap();
+        assert!(text.starts_with("Post report on lemmy.world: <script>\nReason: Spam & scam"));
+    }
+}
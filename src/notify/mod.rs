@@ -1,42 +1,121 @@
 use ::ntfy::{dispatcher, Auth};
 use async_trait::async_trait;
-use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView};
+use serde::Serialize;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
 use tokio_util::sync::CancellationToken;
-use webhook::client::WebhookClient;
+use crate::db::DbPool;
 use crate::env::EnvVariables;
+use crate::lemmy::ClientRegistry;
+use crate::models::federation_gap::FederationGap;
+use crate::models::instance_status::InstanceStatus;
+use crate::models::open_report_count::OpenReportCount;
+use crate::report::{ReportKind, ReportUpdate};
+use crate::templates::Templates;
 
 pub mod discord;
 pub mod console;
+pub mod email;
+pub mod home_assistant;
+pub mod matrix;
 pub mod mqtt;
 pub mod ntfy;
+pub mod telegram;
+pub mod webhook;
 
 #[async_trait]
 pub trait NotifyReport: Send + Sync {
+    /// Identifies the notifier in the outbox, so must stay the same between restarts
+    fn name(&self) -> &str;
     async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()>;
     async fn notify_comment(&self, source_domain: &str, report: &CommentReportView) -> anyhow::Result<()>;
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()>;
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()>;
+    /// Called when a known report is resolved, reopened or its content removed
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()>;
+    /// Called periodically with the federation of reports between each pair of instances
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()>;
+    /// Called instead of a new notification when an already sent report federated to another instance
+    async fn notify_copy(&self, origin_domain: &str, kind: ReportKind, origin_report_id: i32, seen_on: &[String]) -> anyhow::Result<()> {
+        let _ = (origin_domain, kind, origin_report_id, seen_on);
+        Ok(())
+    }
+
+    /// Called every poll interval with the open reports of every known community, for notifiers that publish a state
+    async fn notify_open_reports(&self, counts: &[OpenReportCount]) -> anyhow::Result<()> {
+        let _ = counts;
+        Ok(())
+    }
+
+    /// Called every poll interval with the latest poll of every instance that has credentials
+    async fn notify_instance_statuses(&self, statuses: &[InstanceStatus]) -> anyhow::Result<()> {
+        let _ = statuses;
+        Ok(())
+    }
+}
+
+/// Machine-readable payload of MQTT messages and webhooks
+#[derive(Serialize)]
+pub struct JsonPayload<'a, TReport> {
+    pub source_domain: &'a str,
+    pub report: &'a TReport,
+}
+
+/// One line per instance pair, e.g. `a.com → b.com: 2 missing, 5 federated (avg. lag 12s, max. 40s)`
+pub fn describe_federation_gap(gap: &FederationGap) -> String {
+    let lag = match (gap.average_lag_seconds, gap.max_lag_seconds) {
+        (Some(average), Some(max)) => format!(" (avg. lag {average:.0}s, max. {max:.0}s)"),
+        _ => String::new(),
+    };
+    format!("{} → {}: {} missing, {} federated{lag}", gap.source_domain, gap.target_domain, gap.missing, gap.federated)
 }
 
-pub async fn collect_notifiers(env_vars: &EnvVariables, cancellation_token: CancellationToken) -> anyhow::Result<Vec<Box<dyn NotifyReport>>> {
+const REDACTED: &str = "[redacted]";
+
+/// Removes the message content so it doesn't end up in third party services
+pub fn redact_private_message_report(report: &PrivateMessageReportView) -> PrivateMessageReportView {
+    let mut redacted = report.clone();
+    redacted.private_message.content = REDACTED.to_string();
+    redacted.private_message_report.original_pm_text = REDACTED.to_string();
+    redacted
+}
+
+pub async fn collect_notifiers(env_vars: &EnvVariables, db_pool: &DbPool, clients: &ClientRegistry, cancellation_token: CancellationToken) -> anyhow::Result<Vec<Box<dyn NotifyReport>>> {
     let mut notifiers: Vec<Box<dyn NotifyReport>> = Vec::new();
     notifiers.push(Box::new(console::ConsoleNotifyReport {}));
-    if let Some(webhook) = &env_vars.discord_webhook {
-        let discord_client = WebhookClient::new(webhook.url());
-        notifiers.push(Box::new(discord_client));
-        println!("Discord notifier was enabled.");
-    }
-    if let Some(ntfy) = &env_vars.ntfy {
-        let mut builder = dispatcher::builder(ntfy.host.clone());
-        if let Some(creds) = &ntfy.credentials {
+    for vars in &env_vars.discord {
+        notifiers.push(Box::new(discord::DiscordNotifier::new(vars, db_pool.clone())?));
+        println!("Discord notifier '{}' was enabled.", vars.name);
+    }
+    for vars in &env_vars.ntfy {
+        let mut builder = dispatcher::builder(vars.host.clone());
+        if let Some(creds) = &vars.credentials {
             builder = builder.credentials(Auth::credentials(creds.username.clone(), creds.password.clone()));
         }
         let dispatcher = builder.build_async()?;
-        notifiers.push(Box::new((dispatcher, ntfy.topic.clone())));
-        println!("ntfy.sh notifier was enabled.");
+        let templates = Templates::new(ntfy::DEFAULT_TEMPLATES, &vars.templates)?;
+        notifiers.push(Box::new(ntfy::NtfyNotifier { name: vars.name.clone(), dispatcher, topic: vars.topic.clone(), templates }));
+        println!("ntfy.sh notifier '{}' was enabled.", vars.name);
+    }
+    for vars in &env_vars.mqtt {
+        let mqtt_notifier = mqtt::connect_mqtt(vars, db_pool.clone(), clients.clone(), cancellation_token.clone()).await?;
+        notifiers.push(Box::new(mqtt_notifier));
+        println!("MQTT notifier '{}' was enabled.", vars.name);
+    }
+    for vars in &env_vars.matrix {
+        notifiers.push(Box::new(matrix::MatrixNotifier::new(vars, db_pool.clone())?));
+        println!("Matrix notifier '{}' was enabled.", vars.name);
+    }
+    for vars in &env_vars.telegram {
+        notifiers.push(Box::new(telegram::TelegramNotifier::new(vars, db_pool.clone(), clients.clone(), cancellation_token.clone())?));
+        println!("Telegram notifier '{}' was enabled.", vars.name);
+    }
+    for vars in &env_vars.webhook {
+        notifiers.push(Box::new(webhook::WebhookNotifier::new(vars, cancellation_token.clone())?));
+        println!("Webhook notifier '{}' was enabled.", vars.name);
     }
-    if let Some(vars) = &env_vars.mqtt {
-        let mqtt_client = mqtt::connect_mqtt(vars, cancellation_token.clone()).await?;
-        notifiers.push(Box::new(mqtt_client));
-        println!("MQTT notifier was enabled.");
+    for vars in &env_vars.email {
+        notifiers.push(Box::new(email::EmailNotifier::new(vars, db_pool.clone(), cancellation_token.clone())?));
+        println!("Email notifier '{}' was enabled.", vars.name);
     }
     Ok(notifiers)
 }
\ No newline at end of file
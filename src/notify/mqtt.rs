@@ -1,59 +1,682 @@
+use crate::db::DbPool;
 use crate::env::MqttEnvVariables;
+use crate::crypto;
+use crate::lemmy::ClientRegistry;
+use anyhow::anyhow;
 use async_trait::async_trait;
-use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView};
-use rumqttc::{AsyncClient, MqttOptions, QoS};
-use serde::Serialize;
-use std::time::Duration;
+use lemmy_client::lemmy_api_common::lemmy_db_schema::source::community::Community;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use rumqttc::{AsyncClient, ClientError, Event, EventLoop, LastWill, MqttOptions, Packet, QoS, Transport};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::select;
 use tokio_util::sync::CancellationToken;
-use crate::notify::NotifyReport;
+use crate::format::{fit_json, MQTT_MAX_PACKET_SIZE, MQTT_PAYLOAD_LIMIT};
+use crate::models::federation_gap::FederationGap;
+use crate::models::instance_status::InstanceStatus;
+use crate::models::open_report_count::OpenReportCount;
+use crate::moderation;
+use crate::moderation::ModerationAction;
+use crate::notify::home_assistant::{slug, Discovery, InstanceTopics};
+use crate::notify::{JsonPayload, NotifyReport};
+use crate::report::{ReportKind, ReportUpdate, ReportView};
+use crate::routing::qualified_name;
+use crate::util::{backoff, sleep};
 
-pub async fn connect_mqtt(vars: &MqttEnvVariables, cancellation_token: CancellationToken) -> anyhow::Result<AsyncClient> {
-    let mut options = MqttOptions::new("lemmy-know", vars.host.clone(), vars.port);
+/// Messages wait here until the eventloop sends them, when it's full they're left to the outbox
+const MQTT_BUFFER_SIZE: usize = 100;
+/// Maximum seconds between reconnection attempts
+const MQTT_MAX_RECONNECT_WAIT: u64 = 60;
+/// Commands issued more seconds ago than this, or as far in the future, are rejected, so a published command can't be executed again later
+const COMMAND_MAX_AGE: u64 = 60;
+/// Replaces placeholders of the topic template that don't apply to an event, e.g. `{community}` of registration applications
+const MISSING_TOPIC_LEVEL: &str = "_";
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+pub struct MqttNotifier {
+    pub name: String,
+    pub client: AsyncClient,
+    connection: Arc<ConnectionState>,
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    open_reports_topic: String,
+    status_topic: String,
+    home_assistant: Option<HomeAssistant>,
+    /// Instances of which the discovery configs were published, kept across reconnects so removed instances are still removed
+    discovered: Mutex<HashSet<String>>,
+    /// Set on every connect, the configs are published again in case the broker lost the retained ones
+    rediscover: Arc<AtomicBool>,
+}
+
+/// Shared with the task that keeps the connection, retained on the status topic after every connect
+#[derive(Default, Serialize)]
+struct ConnectionState {
+    connected: AtomicBool,
+    /// Connections after the first one, counts how often the connection was lost
+    reconnects: AtomicU32,
+}
+
+impl ConnectionState {
+    fn connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn reconnects(&self) -> u32 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+}
+
+struct HomeAssistant {
+    discovery: Discovery,
+    report_events_topic: String,
+}
+
+/// Retained payload of the open report topics
+#[derive(Default, Serialize)]
+struct OpenReports {
+    posts: i64,
+    comments: i64,
+    total: i64,
+}
+
+impl OpenReports {
+    fn add(&mut self, count: &OpenReportCount) {
+        self.posts += count.posts;
+        self.comments += count.comments;
+        self.total += count.posts + count.comments;
+    }
+}
+
+fn read_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {e}", path.display()))
+}
+
+/// Also starts executing the commands published to the command topic, when enabled
+pub async fn connect_mqtt(vars: &MqttEnvVariables, db_pool: DbPool, clients: ClientRegistry, cancellation_token: CancellationToken) -> anyhow::Result<MqttNotifier> {
+    let mut options = MqttOptions::new(vars.client_id.clone(), vars.host.clone(), vars.port);
     if let Some(credentials) = &vars.credentials {
         options.set_credentials(credentials.user.clone(), credentials.password.clone());
     }
+    if let Some(tls) = &vars.tls {
+        let transport = match &tls.ca_file {
+            Some(ca_file) => {
+                let client_auth = match &tls.client_auth {
+                    Some((cert_file, key_file)) => Some((read_file(cert_file)?, read_file(key_file)?)),
+                    None => None,
+                };
+                Transport::tls(read_file(ca_file)?, client_auth, None)
+            }
+            None => Transport::tls_with_default_config(),
+        };
+        options.set_transport(transport);
+    }
     options.set_keep_alive(Duration::from_secs(5));
+    options.set_max_packet_size(MQTT_MAX_PACKET_SIZE, MQTT_MAX_PACKET_SIZE);
+    // Published by the broker when the connection is lost, including when lemmy-know stops
+    options.set_last_will(LastWill::new(&vars.availability_topic, OFFLINE, QoS::AtLeastOnce, true));
+
+    let (client, eventloop) = AsyncClient::new(options, MQTT_BUFFER_SIZE);
+    let state = Arc::new(ConnectionState::default());
+    let rediscover = Arc::new(AtomicBool::new(false));
+    let connection = Connection {
+        name: vars.name.clone(),
+        client: client.clone(),
+        availability_topic: vars.availability_topic.clone(),
+        status_topic: vars.status_topic.clone(),
+        state: state.clone(),
+        rediscover: rediscover.clone(),
+        commands: vars.commands.as_ref().map(|commands| Arc::new(CommandHandler {
+            name: vars.name.clone(),
+            client: client.clone(),
+            command_topic: commands.topic.clone(),
+            response_topic: commands.response_topic.clone(),
+            secret: commands.secret.clone(),
+            nonces: Mutex::new(HashMap::new()),
+            db_pool,
+            clients,
+        })),
+    };
+    tokio::spawn(connection.run(eventloop, cancellation_token));
+
+    Ok(MqttNotifier {
+        name: vars.name.clone(),
+        client,
+        connection: state,
+        topic: vars.topic.clone(),
+        qos: vars.qos,
+        retain: vars.retain,
+        open_reports_topic: vars.open_reports_topic.clone(),
+        status_topic: vars.status_topic.clone(),
+        home_assistant: vars.home_assistant.as_ref().map(|(discovery_prefix, report_events_topic)| HomeAssistant {
+            discovery: Discovery {
+                prefix: discovery_prefix.clone(),
+                node_id: slug(&vars.client_id),
+                availability_topic: vars.availability_topic.clone(),
+            },
+            report_events_topic: report_events_topic.clone(),
+        }),
+        discovered: Mutex::new(HashSet::new()),
+        rediscover,
+    })
+}
 
-    let (client, mut eventloop) = AsyncClient::new(options, 10);
-    tokio::spawn({
-        async move {
-            while !cancellation_token.is_cancelled() {
-                if let Err(e) = eventloop.poll().await {
-                    eprintln!("MQTT eventloop error: {:?}", e);
-                    cancellation_token.cancel();
+struct Connection {
+    name: String,
+    client: AsyncClient,
+    availability_topic: String,
+    status_topic: String,
+    state: Arc<ConnectionState>,
+    rediscover: Arc<AtomicBool>,
+    commands: Option<Arc<CommandHandler>>,
+}
+
+impl Connection {
+    /// Keeps the connection to the broker, reconnecting with a growing delay when it's lost.
+    /// Messages that weren't acknowledged yet are sent again once reconnected.
+    async fn run(self, mut eventloop: EventLoop, token: CancellationToken) {
+        let name = &self.name;
+        let mut failures: u32 = 0;
+        let mut connected_before = false;
+        while !token.is_cancelled() {
+            let event = select! {
+                _ = token.cancelled() => break,
+                event = eventloop.poll() => event,
+            };
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    if connected_before {
+                        self.state.reconnects.fetch_add(1, Ordering::Relaxed);
+                    }
+                    connected_before = true;
+                    self.state.connected.store(true, Ordering::Relaxed);
+                    failures = 0;
+                    println!("MQTT notifier '{name}' connected to the broker, {} reconnects so far.", self.state.reconnects());
+                    self.rediscover.store(true, Ordering::Relaxed);
+                    // Not awaited, as the eventloop has to keep running to send it
+                    if let Err(e) = self.client.try_publish(&self.availability_topic, QoS::AtLeastOnce, true, ONLINE) {
+                        eprintln!("MQTT notifier '{name}' failed to publish its availability: {e}");
+                    }
+                    let published = serde_json::to_string(self.state.as_ref()).map_err(anyhow::Error::from)
+                        .and_then(|json| self.client.try_publish(&self.status_topic, QoS::AtLeastOnce, true, json).map_err(Into::into));
+                    if let Err(e) = published {
+                        eprintln!("MQTT notifier '{name}' failed to publish its connection state: {e}");
+                    }
+                    // Subscriptions don't outlive the session
+                    if let Some(commands) = &self.commands {
+                        if let Err(e) = self.client.try_subscribe(&commands.command_topic, QoS::AtLeastOnce) {
+                            eprintln!("MQTT notifier '{name}' failed to subscribe to its command topic: {e}");
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    // Retained commands would be executed again after every reconnect
+                    if let Some(commands) = self.commands.as_ref().filter(|commands| commands.command_topic == publish.topic && !publish.retain) {
+                        tokio::spawn(commands.clone().handle(publish.payload.to_vec()));
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let wait = backoff(1, failures, MQTT_MAX_RECONNECT_WAIT);
+                    failures = failures.saturating_add(1);
+                    match self.state.connected.swap(false, Ordering::Relaxed) {
+                        true => eprintln!("MQTT notifier '{name}' lost the connection to the broker, reconnecting in {wait}s: {e}"),
+                        false => eprintln!("MQTT notifier '{name}' failed to connect to the broker, retrying in {wait}s: {e}"),
+                    }
+                    sleep(Duration::from_secs(wait), &token).await;
                 }
             }
         }
-    });
+    }
+}
+
+/// Published to the command topic, `command` is the JSON of the command as a string, so the signature covers its exact bytes
+#[derive(Deserialize)]
+struct SignedCommand {
+    command: String,
+    /// HMAC-SHA256 of `command` with the command secret as hex
+    signature: String,
+}
+
+/// Part of every signed command, so its signature is only accepted once and only shortly after it was issued
+#[derive(Deserialize)]
+struct Freshness {
+    /// Unix timestamp in seconds
+    issued_at: u64,
+    nonce: String,
+}
 
-    Ok(client)
+/// Moderation command, e.g. `{"id": 1, "action": "resolve", "domain": "lemmy.world", "kind": "post", "report_id": 123}`
+#[derive(Deserialize)]
+struct Command {
+    action: ModerationAction,
+    domain: String,
+    kind: ReportKind,
+    report_id: i32,
 }
 
+/// Published for every command, `id` is copied from the command so responses can be matched to their command
 #[derive(Serialize)]
-struct MqttPayload<'a, TReport> {
-    source_domain: &'a str,
-    report: &'a TReport,
+struct CommandResponse {
+    id: Value,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+struct CommandHandler {
+    name: String,
+    client: AsyncClient,
+    command_topic: String,
+    response_topic: String,
+    secret: String,
+    /// Nonces of the accepted commands with their `issued_at`, forgotten once those commands would be rejected as expired
+    nonces: Mutex<HashMap<String, u64>>,
+    db_pool: DbPool,
+    clients: ClientRegistry,
+}
+
+impl CommandHandler {
+    /// Runs in its own task, as the eventloop has to keep running to send the requests of the command
+    async fn handle(self: Arc<Self>, payload: Vec<u8>) {
+        let (id, command) = {
+            let mut nonces = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+            verify_command(&self.secret, &payload, unix_time(), &mut nonces)
+        };
+        let result = match command {
+            Ok(command) => self.perform(command).await,
+            Err(e) => {
+                eprintln!("MQTT notifier '{}' rejected a command: {e}", self.name);
+                Err(e)
+            }
+        };
+        let response = CommandResponse::new(id, result);
+        let published = match serde_json::to_string(&response) {
+            Ok(json) => self.client.publish(&self.response_topic, QoS::AtLeastOnce, false, json).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = published {
+            eprintln!("MQTT notifier '{}' failed to publish a command response: {e}", self.name);
+        }
+    }
+
+    async fn perform(&self, command: Value) -> anyhow::Result<()> {
+        let command: Command = serde_json::from_value(command)?;
+        moderation::perform(&self.db_pool, &self.clients, &command.domain, command.kind, command.report_id, command.action).await?;
+        println!("MQTT notifier '{}' performed {:?} on {} report {} of {}", self.name, command.action, command.kind, command.report_id, command.domain);
+        Ok(())
+    }
+}
+
+impl CommandResponse {
+    fn new(id: Value, result: anyhow::Result<()>) -> Self {
+        CommandResponse {
+            id,
+            success: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default()
+}
+
+/// Returns the `id` of the command, which is also answered when the command is rejected,
+/// and the command when it's signed with the secret, recently issued and its nonce wasn't seen before
+fn verify_command(secret: &str, payload: &[u8], now: u64, nonces: &mut HashMap<String, u64>) -> (Value, anyhow::Result<Value>) {
+    let signed = match serde_json::from_slice::<SignedCommand>(payload) {
+        Ok(signed) => signed,
+        Err(e) => return (Value::Null, Err(anyhow!("Commands have to be signed: {e}"))),
+    };
+    let command = serde_json::from_str::<Value>(&signed.command);
+    let id = command.as_ref().ok().and_then(|command| command.get("id")).cloned().unwrap_or(Value::Null);
+    if !crypto::verify(secret, signed.command.as_bytes(), &signed.signature) {
+        return (id, Err(anyhow!("Invalid command signature")));
+    }
+    let command = match command {
+        Ok(command) => command,
+        Err(e) => return (id, Err(e.into())),
+    };
+    let freshness = match Freshness::deserialize(&command) {
+        Ok(freshness) => freshness,
+        Err(e) => return (id, Err(anyhow!("Commands need an issued_at and a nonce: {e}"))),
+    };
+    if freshness.issued_at.abs_diff(now) > COMMAND_MAX_AGE {
+        return (id, Err(anyhow!("Command is expired")));
+    }
+    nonces.retain(|_, issued_at| issued_at.abs_diff(now) <= COMMAND_MAX_AGE);
+    if nonces.insert(freshness.nonce, freshness.issued_at).is_some() {
+        return (id, Err(anyhow!("Command was already received")));
+    }
+    (id, Ok(command))
+}
+
+/// Serializes the payload, shortening long texts like post bodies so it fits into a packet
+fn to_json<T: Serialize>(payload: &T) -> anyhow::Result<String> {
+    let mut value = serde_json::to_value(payload)?;
+    Ok(fit_json(&mut value, MQTT_PAYLOAD_LIMIT)?)
+}
+
+/// Fills in the topic template, e.g. `lemmy-know/{domain}/{community}/{kind}` becomes `lemmy-know/lemmy.world/technology@lemmy.world/post`
+fn topic(template: &str, kind: &str, domain: Option<&str>, community: Option<&Community>) -> String {
+    let community = community.and_then(|community| qualified_name(&community.name, &community.actor_id.to_string()));
+    template
+        .replace("{kind}", &topic_level(Some(kind)))
+        .replace("{domain}", &topic_level(domain))
+        .replace("{community}", &topic_level(community.as_deref()))
+}
+
+/// Separators and wildcards in values would change which subscriptions match
+fn topic_level(value: Option<&str>) -> String {
+    match value {
+        Some(value) if !value.is_empty() => value.replace(['/', '+', '#'], "_"),
+        _ => MISSING_TOPIC_LEVEL.to_string(),
+    }
+}
+
+impl MqttNotifier {
+    /// Doesn't wait for the broker, so a notification only fails when the buffer is full
+    fn publish(&self, kind: &str, domain: Option<&str>, community: Option<&Community>, json: String) -> anyhow::Result<()> {
+        // Buffered messages are lost when lemmy-know stops, so while disconnected the outbox keeps and retries them
+        if !self.connection.connected() {
+            return Err(anyhow!("Not connected to the MQTT broker"));
+        }
+        let topic = topic(&self.topic, kind, domain, community);
+        match self.client.try_publish(topic, self.qos, self.retain, json) {
+            Ok(()) => Ok(()),
+            Err(ClientError::TryRequest(_)) => Err(anyhow!("The MQTT buffer is full")),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn publish_state<T: Serialize>(&self, topic: String, state: &T) -> anyhow::Result<()> {
+        self.client.publish(topic, self.qos, true, serde_json::to_string(state)?).await?;
+        Ok(())
+    }
+
+    /// Triggers the event entity of the instance, failures don't fail the notification as its message was already published
+    fn publish_report_event(&self, domain: &str, event: Value) {
+        let Some(home_assistant) = &self.home_assistant else {
+            return;
+        };
+        let topic = format!("{}/{}", home_assistant.report_events_topic, topic_level(Some(domain)));
+        let result = to_json(&event).and_then(|json| self.client.try_publish(topic, self.qos, false, json).map_err(Into::into));
+        if let Err(e) = result {
+            eprintln!("MQTT notifier '{}' failed to publish a report event: {e}", self.name);
+        }
+    }
+
+    fn instance_topics(&self, domain: &str, report_events_topic: &str) -> InstanceTopics {
+        let domain = topic_level(Some(domain));
+        InstanceTopics {
+            open_reports: format!("{}/{domain}", self.open_reports_topic),
+            status: format!("{}/{domain}", self.status_topic),
+            events: format!("{report_events_topic}/{domain}"),
+        }
+    }
+
+    /// Adds a device for every new instance and removes those of instances without credentials
+    async fn publish_discovery(&self, home_assistant: &HomeAssistant, statuses: &[InstanceStatus]) -> anyhow::Result<()> {
+        let domains = statuses.iter().map(|status| status.domain.clone()).collect::<HashSet<_>>();
+        let discovered = self.discovered.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let rediscover = self.rediscover.load(Ordering::Relaxed);
+        for domain in domains.iter().filter(|domain| rediscover || !discovered.contains(*domain)) {
+            let topics = self.instance_topics(domain, &home_assistant.report_events_topic);
+            for (topic, config) in home_assistant.discovery.configs(domain, &topics) {
+                self.client.publish(topic, QoS::AtLeastOnce, true, config.to_string()).await?;
+            }
+            self.discovered.lock().unwrap_or_else(|e| e.into_inner()).insert(domain.clone());
+        }
+        for domain in discovered.difference(&domains) {
+            // An empty retained message removes the entity
+            for topic in home_assistant.discovery.config_topics(domain) {
+                self.client.publish(topic, QoS::AtLeastOnce, true, "").await?;
+            }
+            self.discovered.lock().unwrap_or_else(|e| e.into_inner()).remove(domain);
+        }
+        if rediscover {
+            self.rediscover.store(false, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+fn report_event<T: ReportView>(source_domain: &str, report: &T) -> Value {
+    json!({
+        "event_type": T::KIND.as_str(),
+        "report_id": report.report_id(),
+        "community": report.community().and_then(|community| qualified_name(&community.name, &community.actor_id.to_string())),
+        "reason": report.reason(),
+        "summary": report.summary(),
+        "url": report.content_url(source_domain),
+    })
 }
 
 #[async_trait]
-impl NotifyReport for AsyncClient {
+impl NotifyReport for MqttNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()> {
-        let payload = MqttPayload {
+        let payload = JsonPayload {
             source_domain,
             report: &report,
         };
-        let json = serde_json::to_string(&payload)?;
-        self.publish("lemmy-know/post", QoS::AtLeastOnce, false, json).await?;
+        let json = to_json(&payload)?;
+        self.publish("post", Some(source_domain), report.community(), json)?;
+        self.publish_report_event(source_domain, report_event(source_domain, report));
         Ok(())
     }
 
     async fn notify_comment(&self, source_domain: &str, report: &CommentReportView) -> anyhow::Result<()> {
-        let payload = MqttPayload {
+        let payload = JsonPayload {
             source_domain,
             report: &report,
         };
-        let json = serde_json::to_string(&payload)?;
-        self.publish("lemmy-know/comment", QoS::AtLeastOnce, false, json).await?;
+        let json = to_json(&payload)?;
+        self.publish("comment", Some(source_domain), report.community(), json)?;
+        self.publish_report_event(source_domain, report_event(source_domain, report));
+        Ok(())
+    }
+
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()> {
+        let payload = JsonPayload {
+            source_domain,
+            report: &report,
+        };
+        let json = to_json(&payload)?;
+        self.publish("private_message", Some(source_domain), None, json)?;
+        self.publish_report_event(source_domain, report_event(source_domain, report));
+        Ok(())
+    }
+
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()> {
+        let payload = JsonPayload {
+            source_domain,
+            report: &application,
+        };
+        let json = to_json(&payload)?;
+        self.publish("registration_application", Some(source_domain), None, json)?;
+        let event = json!({ "event_type": "registration_application", "username": application.creator.name });
+        self.publish_report_event(source_domain, event);
+        Ok(())
+    }
+
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()> {
+        let payload = JsonPayload {
+            source_domain,
+            report: &update,
+        };
+        let json = to_json(&payload)?;
+        self.publish("update", Some(source_domain), None, json)
+    }
+
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()> {
+        let json = to_json(&gaps)?;
+        self.publish("federation_gaps", None, None, json)
+    }
+
+    /// Publishes the retained status of every polled instance to `{status_topic}/{domain}`
+    async fn notify_instance_statuses(&self, statuses: &[InstanceStatus]) -> anyhow::Result<()> {
+        if !self.connection.connected() {
+            return Ok(());
+        }
+        if let Some(home_assistant) = &self.home_assistant {
+            self.publish_discovery(home_assistant, statuses).await?;
+        }
+        for status in statuses.iter().filter(|status| status.last_poll.is_some()) {
+            self.publish_state(format!("{}/{}", self.status_topic, topic_level(Some(&status.domain))), status).await?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Publishes retained counts to `{open_reports_topic}/{domain}` and `{open_reports_topic}/{domain}/{community}`
+    async fn notify_open_reports(&self, counts: &[OpenReportCount]) -> anyhow::Result<()> {
+        // The next call publishes the current counts anyway, so nothing is buffered while disconnected
+        if !self.connection.connected() {
+            return Ok(());
+        }
+        let mut instances: BTreeMap<&str, OpenReports> = BTreeMap::new();
+        for count in counts {
+            instances.entry(&count.domain).or_default().add(count);
+            let (Some(name), Some(actor_id)) = (&count.community_name, &count.community_actor_id) else {
+                continue;
+            };
+            let mut community = OpenReports::default();
+            community.add(count);
+            let community_name = qualified_name(name, actor_id);
+            let topic = format!("{}/{}/{}", self.open_reports_topic, topic_level(Some(&count.domain)), topic_level(community_name.as_deref()));
+            self.publish_state(topic, &community).await?;
+        }
+        for (domain, instance) in instances {
+            self.publish_state(format!("{}/{}", self.open_reports_topic, topic_level(Some(domain))), &instance).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_topic() {
+        assert_eq!("lemmy-know/post", topic("lemmy-know/{kind}", "post", Some("lemmy.world"), None));
+    }
+
+    #[test]
+    fn missing_values_are_replaced() {
+        let template = "lemmy-know/{domain}/{community}/{kind}";
+        assert_eq!("lemmy-know/_/_/federation_gaps", topic(template, "federation_gaps", None, None));
+        assert_eq!("lemmy-know/lemmy.world/_/registration_application", topic(template, "registration_application", Some("lemmy.world"), None));
+    }
+
+    #[test]
+    fn parse_command() {
+        let command: Command = serde_json::from_str(r#"{"id": "a1", "action": "lock", "domain": "lemmy.world", "kind": "comment", "report_id": 42}"#).unwrap();
+        assert_eq!(ModerationAction::Lock, command.action);
+        assert_eq!(ReportKind::Comment, command.kind);
+        assert_eq!(42, command.report_id);
+    }
+
+    fn signed(secret: &str, command: &str) -> Vec<u8> {
+        json!({ "command": command, "signature": crypto::sign(secret, command.as_bytes()) }).to_string().into_bytes()
+    }
+
+    const NOW: u64 = 1_750_000_000;
+
+    fn command(issued_at: u64, nonce: &str) -> String {
+        json!({ "id": "a1", "action": "resolve", "domain": "lemmy.world", "kind": "post", "report_id": 42, "issued_at": issued_at, "nonce": nonce }).to_string()
+    }
+
+    #[test]
+    fn signed_command() {
+        let (id, command) = verify_command("secret", &signed("secret", &command(NOW, "n1")), NOW, &mut HashMap::new());
+        assert_eq!(json!("a1"), id);
+        assert_eq!(json!("resolve"), command.unwrap()["action"]);
+    }
+
+    #[test]
+    fn reject_unsigned_commands() {
+        let command = command(NOW, "n1");
+        let (id, result) = verify_command("secret", &signed("guess", &command), NOW, &mut HashMap::new());
+        let response = CommandResponse::new(id, result.map(|_| ()));
+        assert_eq!(r#"{"id":"a1","success":false,"error":"Invalid command signature"}"#, serde_json::to_string(&response).unwrap());
+
+        let (id, result) = verify_command("secret", command.as_bytes(), NOW, &mut HashMap::new());
+        assert_eq!(Value::Null, id);
+        assert!(result.unwrap_err().to_string().starts_with("Commands have to be signed"));
+    }
+
+    #[test]
+    fn reject_replayed_commands() {
+        let mut nonces = HashMap::new();
+        let payload = signed("secret", &command(NOW, "n1"));
+        assert!(verify_command("secret", &payload, NOW, &mut nonces).1.is_ok());
+        let (_, result) = verify_command("secret", &payload, NOW + 5, &mut nonces);
+        assert_eq!("Command was already received", result.unwrap_err().to_string());
+        assert!(verify_command("secret", &signed("secret", &command(NOW, "n2")), NOW + 5, &mut nonces).1.is_ok());
+    }
+
+    #[test]
+    fn reject_stale_commands() {
+        let mut nonces = HashMap::new();
+        let (_, result) = verify_command("secret", &signed("secret", &command(NOW - COMMAND_MAX_AGE - 1, "n1")), NOW, &mut nonces);
+        assert_eq!("Command is expired", result.unwrap_err().to_string());
+        let (_, result) = verify_command("secret", &signed("secret", &command(NOW + COMMAND_MAX_AGE + 1, "n2")), NOW, &mut nonces);
+        assert_eq!("Command is expired", result.unwrap_err().to_string());
+
+        let unbound = json!({ "id": "a1", "action": "resolve", "domain": "lemmy.world", "kind": "post", "report_id": 42 }).to_string();
+        let (_, result) = verify_command("secret", &signed("secret", &unbound), NOW, &mut nonces);
+        assert!(result.unwrap_err().to_string().starts_with("Commands need an issued_at and a nonce"));
+        assert!(nonces.is_empty());
+    }
+
+    #[tokio::test]
+    async fn outbox_keeps_notifications() {
+        // The eventloop is never polled, so nothing leaves the buffer
+        let (client, _eventloop) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 1);
+        let notifier = MqttNotifier {
+            name: "mqtt".to_string(),
+            client,
+            connection: Arc::new(ConnectionState::default()),
+            topic: "lemmy-know/{kind}".to_string(),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            open_reports_topic: "lemmy-know/open_reports".to_string(),
+            status_topic: "lemmy-know/status".to_string(),
+            home_assistant: None,
+            discovered: Mutex::new(HashSet::new()),
+            rediscover: Arc::new(AtomicBool::new(false)),
+        };
+        let error = notifier.publish("post", None, None, "{}".to_string()).unwrap_err();
+        assert_eq!("Not connected to the MQTT broker", error.to_string());
+
+        notifier.connection.connected.store(true, Ordering::Relaxed);
+        assert!(notifier.publish("post", None, None, "{}".to_string()).is_ok());
+        let error = notifier.publish("post", None, None, "{}".to_string()).unwrap_err();
+        assert_eq!("The MQTT buffer is full", error.to_string());
+    }
+
+    #[test]
+    fn connection_state_json() {
+        let state = ConnectionState::default();
+        state.connected.store(true, Ordering::Relaxed);
+        state.reconnects.fetch_add(2, Ordering::Relaxed);
+        assert_eq!(r#"{"connected":true,"reconnects":2}"#, serde_json::to_string(&state).unwrap());
+    }
+
+    #[test]
+    fn values_cant_add_levels_or_wildcards() {
+        assert_eq!("a_b_c_", topic_level(Some("a/b+c#")));
+        assert_eq!("_", topic_level(Some("")));
+    }
+}
@@ -1,27 +1,70 @@
+use crate::format::{truncate_bytes, NTFY_MESSAGE_LIMIT};
+use crate::models::federation_gap::FederationGap;
 use crate::notify::NotifyReport;
+use crate::report::ReportUpdate;
+use crate::templates::{federation_gaps_context, registration_application_context, report_context, update_context, Templates};
 use async_trait::async_trait;
-use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView};
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use minijinja::Value;
 use ntfy::prelude::*;
 
+pub const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("post.title", "New Post Report: {{ report.post.name }}"),
+    ("post.message", "{{ report.post_report.reason }}"),
+    ("comment.title", "New Comment Report"),
+    ("comment.message", "{{ report.comment_report.reason }}"),
+    ("private_message.title", "New Private Message Report"),
+    ("private_message.message", "{{ report.private_message_report.reason }}"),
+    ("registration_application.title", "New Registration Application: {{ application.creator.name }}"),
+    ("registration_application.message", "{{ application.registration_application.answer }}\n\nEmail {{ 'verified' if email_verified else 'unverified' }}"),
+    ("update.title", "{{ update.kind }} Report: {{ state }}"),
+    ("update.message", "{{ update.summary }}{% if update.resolver %} (resolved by {{ update.resolver }}){% endif %}"),
+    ("federation_gaps.title", "Federation Gaps"),
+    ("federation_gaps.message", "{{ summary }}"),
+    ("view_reports", "View Reports"),
+    ("view_post", "View Post"),
+    ("view_applications", "View Applications"),
+];
+
+pub struct NtfyNotifier {
+    pub name: String,
+    pub dispatcher: Dispatcher<Async>,
+    pub topic: String,
+    pub templates: Templates,
+}
+
+impl NtfyNotifier {
+    /// Renders the `message` template of the event, cut to a size ntfy still shows as a message
+    fn render_message(&self, event: &str, context: &Value) -> anyhow::Result<String> {
+        let message = self.templates.render(&format!("{event}.message"), context)?;
+        Ok(truncate_bytes(&message, NTFY_MESSAGE_LIMIT).into_owned())
+    }
+}
+
 #[async_trait]
-impl NotifyReport for (Dispatcher<Async>, String) {
+impl NotifyReport for NtfyNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()> {
         let post_url = format!("https://{}/post/{}", source_domain, report.post.id);
         let reports_url = format!("https://{}/reports", source_domain);
+        let context = report_context(source_domain, report);
 
-        let payload = Payload::new(self.1.as_str())
-            .message(report.post_report.reason.as_str())
-            .title(&format!("New Post Report: {}", report.post.name))
+        let payload = Payload::new(self.topic.as_str())
+            .message(self.render_message("post", &context)?)
+            .title(&self.templates.render("post.title", &context)?)
             .tags(["post", "report"])
             .priority(Priority::Default)
             // TODO check if this can be safely turned on without leaking an IP through embeds
             .markdown(false)
             .click(Url::parse(&post_url)?)
             .actions(vec![
-                Action::new(ActionType::View, "View Reports", Url::parse(&reports_url)?)
+                Action::new(ActionType::View, self.templates.render("view_reports", &context)?, Url::parse(&reports_url)?)
             ]);
 
-        self.0.send(&payload).await?;
+        self.dispatcher.send(&payload).await?;
 
         Ok(())
     }
@@ -30,21 +73,97 @@ impl NotifyReport for (Dispatcher<Async>, String) {
         let post_url = format!("https://{}/post/{}", source_domain, report.post.id);
         let comment_url = format!("{}/{}", post_url, report.comment.id);
         let reports_url = format!("https://{}/reports", source_domain);
+        let context = report_context(source_domain, report);
 
-        let payload = Payload::new(self.1.as_str())
-            .message(report.comment_report.reason.as_str())
-            .title("New Comment Report")
+        let payload = Payload::new(self.topic.as_str())
+            .message(self.render_message("comment", &context)?)
+            .title(&self.templates.render("comment.title", &context)?)
             .tags(["comment", "report"])
             .priority(Priority::Default)
             // TODO check if this can be safely turned on without leaking an IP through embeds
             .markdown(false)
             .click(Url::parse(&comment_url)?)
             .actions(vec![
-                Action::new(ActionType::View, "View Reports", Url::parse(&reports_url)?),
-                Action::new(ActionType::View, "View Post", Url::parse(&post_url)?),
+                Action::new(ActionType::View, self.templates.render("view_reports", &context)?, Url::parse(&reports_url)?),
+                Action::new(ActionType::View, self.templates.render("view_post", &context)?, Url::parse(&post_url)?),
+            ]);
+
+        self.dispatcher.send(&payload).await?;
+
+        Ok(())
+    }
+
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()> {
+        let reports_url = format!("https://{}/reports", source_domain);
+        let context = report_context(source_domain, report);
+
+        let payload = Payload::new(self.topic.as_str())
+            .message(self.render_message("private_message", &context)?)
+            .title(&self.templates.render("private_message.title", &context)?)
+            .tags(["private_message", "report"])
+            .priority(Priority::Default)
+            // TODO check if this can be safely turned on without leaking an IP through embeds
+            .markdown(false)
+            .click(Url::parse(&reports_url)?)
+            .actions(vec![
+                Action::new(ActionType::View, self.templates.render("view_reports", &context)?, Url::parse(&reports_url)?)
+            ]);
+
+        self.dispatcher.send(&payload).await?;
+
+        Ok(())
+    }
+
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()> {
+        let applications_url = format!("https://{}/registration_applications", source_domain);
+        let context = registration_application_context(source_domain, application);
+
+        let payload = Payload::new(self.topic.as_str())
+            .message(self.render_message("registration_application", &context)?)
+            .title(&self.templates.render("registration_application.title", &context)?)
+            .tags(["registration_application"])
+            .priority(Priority::Default)
+            // TODO check if this can be safely turned on without leaking an IP through embeds
+            .markdown(false)
+            .click(Url::parse(&applications_url)?)
+            .actions(vec![
+                Action::new(ActionType::View, self.templates.render("view_applications", &context)?, Url::parse(&applications_url)?)
             ]);
 
-        self.0.send(&payload).await?;
+        self.dispatcher.send(&payload).await?;
+
+        Ok(())
+    }
+
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()> {
+        let reports_url = format!("https://{}/reports", source_domain);
+        let context = update_context(source_domain, update);
+
+        let payload = Payload::new(self.topic.as_str())
+            .message(self.render_message("update", &context)?)
+            .title(&self.templates.render("update.title", &context)?)
+            .tags([update.kind.as_str(), "report", "update"])
+            .priority(Priority::Low)
+            // TODO check if this can be safely turned on without leaking an IP through embeds
+            .markdown(false)
+            .click(Url::parse(&reports_url)?);
+
+        self.dispatcher.send(&payload).await?;
+
+        Ok(())
+    }
+
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()> {
+        let context = federation_gaps_context(gaps);
+
+        let payload = Payload::new(self.topic.as_str())
+            .message(self.render_message("federation_gaps", &context)?)
+            .title(&self.templates.render("federation_gaps.title", &context)?)
+            .tags(["federation"])
+            .priority(Priority::Low)
+            .markdown(false);
+
+        self.dispatcher.send(&payload).await?;
 
         Ok(())
     }
@@ -0,0 +1,623 @@
+use crate::db::DbPool;
+use crate::env::TelegramEnvVariables;
+use crate::format::{strip_html, truncate_utf16, utf16_len, TELEGRAM_MESSAGE_LIMIT};
+use crate::lemmy::ClientRegistry;
+use crate::models::federation_gap::FederationGap;
+use crate::moderation;
+use crate::moderation::ModerationAction;
+use crate::notify::NotifyReport;
+use crate::report::{ReportKind, ReportUpdate, ReportView};
+use crate::templates::{federation_gaps_context, registration_application_context, report_context, update_context, Templates};
+use crate::util::{backoff, sleep};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use minijinja::{context, Value};
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Values inserted into the `html` templates are HTML escaped, long texts are truncated to stay within Telegram's message limit
+pub const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("post.html", "<b>Post report on {{ source_domain }}</b>\n<a href=\"{{ url }}\">{{ report.post.name|truncate(300) }}</a>{% if report.post.body %}\n<blockquote>{{ report.post.body|truncate(2000) }}</blockquote>{% endif %}\n<b>Reason:</b> {{ report.post_report.reason|truncate(500) }}\n<b>Author:</b> {{ report.post_creator.actor_id }}"),
+    ("comment.html", "<b>Comment report on {{ source_domain }}</b>\n<blockquote>{{ report.comment.content|truncate(2000) }}</blockquote>\n<b>Reason:</b> {{ report.comment_report.reason|truncate(500) }}\n<b>Author:</b> {{ report.comment_creator.actor_id }}"),
+    ("private_message.html", "<b>Private message report on {{ source_domain }}</b>\n<blockquote>{{ report.private_message.content|truncate(2000) }}</blockquote>\n<b>Reason:</b> {{ report.private_message_report.reason|truncate(500) }}\n<b>Author:</b> {{ report.private_message_creator.actor_id }}"),
+    ("registration_application.html", "<b>Registration application on {{ source_domain }}</b>: {{ application.creator.name }}\n<blockquote>{{ application.registration_application.answer|truncate(2000) }}</blockquote>\nEmail {{ 'verified' if email_verified else 'unverified' }}"),
+    ("update.html", "<b>{{ update.kind }} report {{ state }}</b>: {{ update.summary|truncate(300) }}{% if update.resolver %} (by {{ update.resolver }}){% endif %}"),
+    ("federation_gaps.html", "<b>Federation gaps</b>\n<pre>{{ summary|truncate(3500) }}</pre>"),
+    ("resolve", "Resolve"),
+    ("remove", "Remove content"),
+    ("open", "Open"),
+    ("resolved", "Resolved"),
+    ("removed", "Content removed"),
+    ("not_allowed", "You're not allowed to act on reports"),
+    ("failed", "Failed: {{ error }}"),
+];
+
+const HTML_PARSE_MODE: &str = "HTML";
+
+/// Telegram drops callback data above this size
+const MAX_CALLBACK_DATA: usize = 64;
+
+/// Telegram rejects longer answers to button presses
+const MAX_ANSWER_LENGTH: usize = 200;
+
+/// Seconds a request for updates waits for a button to be pressed before it returns empty
+const LONG_POLL_TIMEOUT: u64 = 30;
+
+#[derive(Deserialize)]
+struct TelegramResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SendMessage<'a> {
+    chat_id: &'a str,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<&'static str>,
+    /// Previews are disabled so Telegram doesn't fetch reported content
+    link_preview_options: LinkPreviewOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+#[derive(Serialize)]
+struct LinkPreviewOptions {
+    is_disabled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InlineKeyboardMarkup {
+    inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InlineKeyboardButton {
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    callback_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct GetUpdates {
+    offset: i64,
+    timeout: u64,
+    allowed_updates: [&'static str; 1],
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    id: String,
+    from: User,
+    message: Option<Message>,
+    data: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    message_id: i64,
+    chat: Chat,
+    reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+    /// Only set for public chats
+    username: Option<String>,
+}
+
+impl Chat {
+    /// The configured chat ID is either numeric or `@username`
+    fn is(&self, chat_id: &str) -> bool {
+        match chat_id.strip_prefix('@') {
+            Some(username) => self.username.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(username)),
+            None => chat_id.parse() == Ok(self.id),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AnswerCallbackQuery<'a> {
+    callback_query_id: &'a str,
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+struct EditMessageReplyMarkup {
+    chat_id: i64,
+    message_id: i64,
+    reply_markup: InlineKeyboardMarkup,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CallbackAction {
+    Resolve,
+    Remove,
+}
+
+/// What a button does, encoded in its callback data as `action:kind:report_id:domain`
+#[derive(Debug, PartialEq)]
+struct Callback {
+    action: CallbackAction,
+    kind: ReportKind,
+    report_id: i32,
+    domain: String,
+}
+
+impl Callback {
+    /// `None` when the domain is too long to fit into the callback data
+    fn encode(&self) -> Option<String> {
+        let action = match self.action {
+            CallbackAction::Resolve => "r",
+            CallbackAction::Remove => "x",
+        };
+        let kind = match self.kind {
+            ReportKind::Post => "p",
+            ReportKind::Comment => "c",
+            ReportKind::PrivateMessage => "m",
+        };
+        let data = format!("{action}:{kind}:{}:{}", self.report_id, self.domain);
+        (data.len() <= MAX_CALLBACK_DATA).then_some(data)
+    }
+
+    fn decode(data: &str) -> Option<Self> {
+        let mut parts = data.splitn(4, ':');
+        let action = match parts.next()? {
+            "r" => CallbackAction::Resolve,
+            "x" => CallbackAction::Remove,
+            _ => return None,
+        };
+        let kind = match parts.next()? {
+            "p" => ReportKind::Post,
+            "c" => ReportKind::Comment,
+            "m" => ReportKind::PrivateMessage,
+            _ => return None,
+        };
+        let report_id = parts.next()?.parse().ok()?;
+        let domain = parts.next()?.to_string();
+        Some(Callback { action, kind, report_id, domain })
+    }
+}
+
+/// Access to the Bot API, shared by the notifier and the task that handles pressed buttons
+struct TelegramBot {
+    http: reqwest::Client,
+    api_url: Url,
+    bot_token: String,
+    templates: Templates,
+}
+
+impl TelegramBot {
+    /// `{api_url}/bot{token}/{method}`
+    fn method_url(&self, method: &str) -> anyhow::Result<Url> {
+        let mut url = self.api_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Telegram API url can't be a base"))?
+            .pop_if_empty()
+            .push(&format!("bot{}", self.bot_token))
+            .push(method);
+        Ok(url)
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, body: &impl Serialize) -> anyhow::Result<T> {
+        // The URL contains the bot token, so it's left out of errors that end up in logs and the outbox
+        let response: TelegramResponse<T> = self.http
+            .post(self.method_url(method)?)
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| e.without_url())?
+            .json()
+            .await
+            .map_err(|e| e.without_url())?;
+        match response.result {
+            Some(result) if response.ok => Ok(result),
+            _ => Err(anyhow!("Telegram {method} failed: {}", response.description.unwrap_or_default())),
+        }
+    }
+}
+
+pub struct TelegramNotifier {
+    name: String,
+    chat_id: String,
+    buttons: bool,
+    bot: Arc<TelegramBot>,
+}
+
+impl TelegramNotifier {
+    /// Also starts handling the buttons of sent messages until the token is cancelled, when they are enabled
+    pub fn new(vars: &TelegramEnvVariables, db_pool: DbPool, clients: ClientRegistry, cancellation_token: CancellationToken) -> anyhow::Result<Self> {
+        let bot = Arc::new(TelegramBot {
+            http: reqwest::Client::new(),
+            api_url: vars.api_url.clone(),
+            bot_token: vars.bot_token.clone(),
+            templates: Templates::new(DEFAULT_TEMPLATES, &vars.templates)?,
+        });
+        if vars.buttons {
+            let handler = CallbackHandler {
+                name: vars.name.clone(),
+                bot: bot.clone(),
+                db_pool,
+                clients,
+                chat_id: vars.chat_id.clone(),
+                allowed_users: vars.allowed_users.clone(),
+            };
+            tokio::spawn(handler.run(cancellation_token));
+        }
+        Ok(TelegramNotifier { name: vars.name.clone(), chat_id: vars.chat_id.clone(), buttons: vars.buttons, bot })
+    }
+
+    async fn send(&self, event: &str, context: &Value, reply_markup: Option<InlineKeyboardMarkup>) -> anyhow::Result<()> {
+        let html = self.bot.templates.render(&format!("{event}.html"), context)?;
+        // Cutting the HTML could break its markup, so overlong messages are sent as plain text
+        let (text, parse_mode) = match utf16_len(&html) <= TELEGRAM_MESSAGE_LIMIT {
+            true => (html, Some(HTML_PARSE_MODE)),
+            false => (truncate_utf16(&strip_html(&html), TELEGRAM_MESSAGE_LIMIT).into_owned(), None),
+        };
+        let message = SendMessage {
+            chat_id: &self.chat_id,
+            text,
+            parse_mode,
+            link_preview_options: LinkPreviewOptions { is_disabled: true },
+            reply_markup,
+        };
+        self.bot.call::<serde_json::Value>("sendMessage", &message).await?;
+        Ok(())
+    }
+
+    fn open_button(&self, context: &Value, url: String) -> anyhow::Result<InlineKeyboardButton> {
+        Ok(InlineKeyboardButton {
+            text: self.bot.templates.render("open", context)?,
+            callback_data: None,
+            url: Some(url),
+        })
+    }
+
+    async fn send_report<T: ReportView>(&self, source_domain: &str, event: &str, report: &T) -> anyhow::Result<()> {
+        let context = report_context(source_domain, report);
+        let callback = |action| Callback { action, kind: T::KIND, report_id: report.report_id(), domain: source_domain.to_string() };
+
+        let mut actions = Vec::new();
+        if self.buttons {
+            actions.extend(callback_button(self.bot.templates.render("resolve", &context)?, callback(CallbackAction::Resolve)));
+            // Lemmy has no way to remove private messages
+            if T::KIND != ReportKind::PrivateMessage {
+                actions.extend(callback_button(self.bot.templates.render("remove", &context)?, callback(CallbackAction::Remove)));
+            }
+            if actions.is_empty() {
+                eprintln!("Telegram notifier '{}' sent report {} of {source_domain} without buttons, the domain is too long for their callback data", self.name, report.report_id());
+            }
+        }
+        let url = report.content_url(source_domain).unwrap_or_else(|| format!("https://{source_domain}/reports"));
+        let mut inline_keyboard = vec![actions, vec![self.open_button(&context, url)?]];
+        inline_keyboard.retain(|row| !row.is_empty());
+        let keyboard = InlineKeyboardMarkup { inline_keyboard };
+        self.send(event, &context, Some(keyboard)).await
+    }
+}
+
+/// `None` when the callback doesn't fit into the button
+fn callback_button(label: String, callback: Callback) -> Option<InlineKeyboardButton> {
+    callback.encode().map(|data| InlineKeyboardButton {
+        text: label,
+        callback_data: Some(data),
+        url: None,
+    })
+}
+
+#[async_trait]
+impl NotifyReport for TelegramNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, "post", report).await
+    }
+
+    async fn notify_comment(&self, source_domain: &str, report: &CommentReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, "comment", report).await
+    }
+
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, "private_message", report).await
+    }
+
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()> {
+        let context = registration_application_context(source_domain, application);
+        let keyboard = InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![self.open_button(&context, format!("https://{source_domain}/registration_applications"))?]],
+        };
+        self.send("registration_application", &context, Some(keyboard)).await
+    }
+
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()> {
+        self.send("update", &update_context(source_domain, update), None).await
+    }
+
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()> {
+        self.send("federation_gaps", &federation_gaps_context(gaps), None).await
+    }
+}
+
+/// Performs the actions of pressed buttons with the accounts that poll the instance of the report
+struct CallbackHandler {
+    name: String,
+    bot: Arc<TelegramBot>,
+    db_pool: DbPool,
+    clients: ClientRegistry,
+    /// Presses of buttons in other chats are ignored, e.g. when a message was forwarded
+    chat_id: String,
+    /// Telegram user IDs that may act on reports
+    allowed_users: Vec<i64>,
+}
+
+impl CallbackHandler {
+    /// The first request only skips the presses from before the start, they could be long outdated.
+    /// Its offset of -1 returns just the last update and confirms the ones before it.
+    async fn run(self, token: CancellationToken) {
+        let mut offset = -1;
+        let mut failures: u32 = 0;
+        while !token.is_cancelled() {
+            let skipping = offset < 0;
+            let timeout = if skipping { 0 } else { LONG_POLL_TIMEOUT };
+            let request = GetUpdates { offset, timeout, allowed_updates: ["callback_query"] };
+            let updates = tokio::select! {
+                _ = token.cancelled() => break,
+                updates = self.bot.call::<Vec<Update>>("getUpdates", &request) => updates,
+            };
+            match updates {
+                Ok(updates) if skipping => {
+                    failures = 0;
+                    offset = updates.iter().map(|update| update.update_id + 1).max().unwrap_or(0);
+                }
+                Ok(updates) => {
+                    failures = 0;
+                    for update in updates {
+                        offset = offset.max(update.update_id + 1);
+                        if let Some(query) = update.callback_query {
+                            self.handle(query).await;
+                        }
+                    }
+                }
+                Err(e) => {
+                    failures = failures.saturating_add(1);
+                    let wait = backoff(1, failures, 60);
+                    eprintln!("Telegram notifier '{}' failed to receive button presses: {e}, retrying in {wait}s", self.name);
+                    sleep(Duration::from_secs(wait), &token).await;
+                }
+            }
+        }
+    }
+
+    async fn handle(&self, query: CallbackQuery) {
+        let answer = match self.is_allowed(&query) {
+            true => self.perform(&query).await,
+            false => self.bot.templates.render("not_allowed", &Value::UNDEFINED),
+        };
+        let text = answer.unwrap_or_else(|e| {
+            eprintln!("Telegram notifier '{}' failed to act on a report: {e}", self.name);
+            self.bot.templates.render("failed", &context! { error => e.to_string() }).unwrap_or_else(|_| e.to_string())
+        });
+        let text = truncate_utf16(&text, MAX_ANSWER_LENGTH);
+        let answer = AnswerCallbackQuery { callback_query_id: &query.id, text: &text };
+        if let Err(e) = self.bot.call::<bool>("answerCallbackQuery", &answer).await {
+            eprintln!("Telegram notifier '{}' failed to answer a button press: {e}", self.name);
+        }
+    }
+
+    /// Only allowed users may act, and only on messages in the configured chat
+    fn is_allowed(&self, query: &CallbackQuery) -> bool {
+        let in_chat = query.message.as_ref().is_some_and(|message| message.chat.is(&self.chat_id));
+        in_chat && self.allowed_users.contains(&query.from.id)
+    }
+
+    /// Returns the text shown to the user who pressed the button
+    async fn perform(&self, query: &CallbackQuery) -> anyhow::Result<String> {
+        let callback = query.data.as_deref()
+            .and_then(Callback::decode)
+            .ok_or_else(|| anyhow!("Unknown button"))?;
+        let action = match callback.action {
+            CallbackAction::Resolve => ModerationAction::Resolve,
+            CallbackAction::Remove => ModerationAction::Remove,
+        };
+        moderation::perform(&self.db_pool, &self.clients, &callback.domain, callback.kind, callback.report_id, action).await?;
+
+        if let Some(message) = &query.message {
+            self.remove_action_buttons(message).await;
+        }
+        let template = match callback.action {
+            CallbackAction::Resolve => "resolved",
+            CallbackAction::Remove => "removed",
+        };
+        self.bot.templates.render(template, &Value::UNDEFINED)
+    }
+
+    /// Keeps only the link buttons, so an action can't be performed twice
+    async fn remove_action_buttons(&self, message: &Message) {
+        let Some(markup) = &message.reply_markup else {
+            return;
+        };
+        let inline_keyboard = markup.inline_keyboard
+            .iter()
+            .map(|row| row.iter()
+                .filter(|button| button.callback_data.is_none())
+                .map(|button| InlineKeyboardButton { text: button.text.clone(), callback_data: None, url: button.url.clone() })
+                .collect::<Vec<_>>())
+            .filter(|row| !row.is_empty())
+            .collect();
+        let edit = EditMessageReplyMarkup {
+            chat_id: message.chat.id,
+            message_id: message.message_id,
+            reply_markup: InlineKeyboardMarkup { inline_keyboard },
+        };
+        if let Err(e) = self.bot.call::<serde_json::Value>("editMessageReplyMarkup", &edit).await {
+            eprintln!("Telegram notifier '{}' failed to remove buttons: {e}", self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel_async::pooled_connection::deadpool::Pool;
+    use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// Stand-in Bot API that answers one request per result and returns the requests
+    fn bot_api(results: Vec<serde_json::Value>) -> (Url, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for result in results {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+                requests.push(request);
+
+                let response = json!({ "ok": true, "result": result }).to_string();
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}", response.len()).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    /// Press of a resolve button by the user on a message in the chat
+    fn press(update_id: i64, user_id: i64, chat_id: i64) -> serde_json::Value {
+        json!({
+            "update_id": update_id,
+            "callback_query": {
+                "id": format!("q{update_id}"),
+                "from": { "id": user_id },
+                "message": { "message_id": 1, "chat": { "id": chat_id } },
+                "data": "r:p:42:lemmy.world",
+            },
+        })
+    }
+
+    fn method_and_body(request: &str) -> (&str, serde_json::Value) {
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        let path = head.split(' ').nth(1).unwrap();
+        (path.rsplit('/').next().unwrap(), serde_json::from_str(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn refuse_presses_of_others() {
+        let (api_url, server) = bot_api(vec![
+            json!([press(5, 1, 10)]),
+            json!([press(6, 2, 10), press(7, 1, 20)]),
+            json!(true),
+            json!(true),
+            json!([]),
+        ]);
+        let handler = CallbackHandler {
+            name: "telegram".to_string(),
+            bot: Arc::new(TelegramBot {
+                http: reqwest::Client::new(),
+                api_url,
+                bot_token: "123:abc".to_string(),
+                templates: Templates::new(DEFAULT_TEMPLATES, &Default::default()).unwrap(),
+            }),
+            // Never connected, refused presses don't reach the database
+            db_pool: Pool::builder(AsyncDieselConnectionManager::new("postgres://localhost/unused")).build().unwrap(),
+            clients: ClientRegistry::default(),
+            chat_id: "10".to_string(),
+            allowed_users: vec![1],
+        };
+        let token = CancellationToken::new();
+        let task = tokio::spawn(handler.run(token.clone()));
+        let requests = tokio::task::spawn_blocking(move || server.join().unwrap()).await.unwrap();
+        token.cancel();
+        task.await.unwrap();
+
+        let requests: Vec<_> = requests.iter().map(|request| method_and_body(request)).collect();
+        // The press from before the start is skipped
+        assert_eq!("getUpdates", requests[0].0);
+        assert_eq!(json!(-1), requests[0].1["offset"]);
+        assert_eq!(json!(0), requests[0].1["timeout"]);
+        assert_eq!(json!(6), requests[1].1["offset"]);
+        for (request, query_id) in requests[2..4].iter().zip(["q6", "q7"]) {
+            assert_eq!("answerCallbackQuery", request.0);
+            assert_eq!(json!(query_id), request.1["callback_query_id"]);
+            assert_eq!(json!("You're not allowed to act on reports"), request.1["text"]);
+        }
+        assert_eq!("getUpdates", requests[4].0);
+        assert_eq!(json!(8), requests[4].1["offset"]);
+    }
+
+    #[test]
+    fn callback_round_trip() {
+        let callback = Callback { action: CallbackAction::Remove, kind: ReportKind::Comment, report_id: 1234, domain: "lemmy.world".to_string() };
+        let data = callback.encode().unwrap();
+        assert_eq!("x:c:1234:lemmy.world", data);
+        assert_eq!(Some(callback), Callback::decode(&data));
+        assert_eq!(None, Callback::decode("y:c:1234:lemmy.world"));
+    }
+
+    #[test]
+    fn callback_data_is_limited() {
+        let callback = Callback { action: CallbackAction::Resolve, kind: ReportKind::Post, report_id: 1, domain: "a".repeat(60) };
+        assert_eq!(None, callback.encode());
+    }
+
+    #[test]
+    fn method_url_of_mock_server() {
+        let bot = TelegramBot {
+            http: reqwest::Client::new(),
+            api_url: Url::parse("http://localhost:8081").unwrap(),
+            bot_token: "123:abc".to_string(),
+            templates: Templates::new(DEFAULT_TEMPLATES, &Default::default()).unwrap(),
+        };
+        assert_eq!("http://localhost:8081/bot123:abc/sendMessage", bot.method_url("sendMessage").unwrap().as_str());
+    }
+
+    #[test]
+    fn only_configured_chat() {
+        let chat = Chat { id: -1001234567890, username: Some("LemmyMods".to_string()) };
+        assert!(chat.is("-1001234567890"));
+        assert!(chat.is("@lemmymods"));
+        assert!(!chat.is("-1009876543210"));
+        assert!(!chat.is("@othermods"));
+        assert!(!Chat { id: 1, username: None }.is("@lemmymods"));
+    }
+}
@@ -0,0 +1,301 @@
+use crate::crypto;
+use crate::env::WebhookEnvVariables;
+use crate::models::federation_gap::FederationGap;
+use crate::notify::{JsonPayload, NotifyReport};
+use crate::report::{ReportUpdate, ReportView};
+use crate::stupid;
+use crate::templates::{federation_gaps_context, registration_application_context, report_context, update_context, Templates};
+use crate::util::{backoff, sleep};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use minijinja::{context, Value};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::{StatusCode, Url};
+use serde::Serialize;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Only used when overridden, the JSON payload is sent as is otherwise
+pub const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("body", "{{ payload|tojson }}"),
+];
+
+pub const EVENT_HEADER: &str = "X-Lemmy-Know-Event";
+/// `sha256=` followed by the HMAC-SHA256 of the body as hex
+pub const SIGNATURE_HEADER: &str = "X-Lemmy-Know-Signature";
+/// Same for every attempt to deliver a notification, so receivers can ignore duplicates
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+/// Requests that take longer are retried like connection errors
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct WebhookNotifier {
+    name: String,
+    http: reqwest::Client,
+    url: Url,
+    headers: HeaderMap,
+    secret: Option<String>,
+    retries: u32,
+    templates: Templates,
+    custom_body: bool,
+    /// Stops the retries on shutdown
+    cancellation_token: CancellationToken,
+}
+
+impl WebhookNotifier {
+    pub fn new(vars: &WebhookEnvVariables, cancellation_token: CancellationToken) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(&vars.content_type)?);
+        for (name, value) in &vars.headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| anyhow!("Invalid header name '{name}'"))?;
+            let header_value = HeaderValue::from_str(value).map_err(|_| anyhow!("Invalid value of header '{name}'"))?;
+            headers.insert(header_name, header_value);
+        }
+        Ok(WebhookNotifier {
+            name: vars.name.clone(),
+            http: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            url: vars.url.clone(),
+            headers,
+            secret: vars.secret.clone(),
+            retries: vars.retries,
+            templates: Templates::new(DEFAULT_TEMPLATES, &vars.templates)?,
+            custom_body: vars.templates.contains_key("body"),
+            cancellation_token,
+        })
+    }
+
+    fn body<T: Serialize>(&self, event: &str, payload: &T, context: Value) -> anyhow::Result<String> {
+        match self.custom_body {
+            true => self.templates.render("body", &context! { event, payload => Value::from_serialize(payload), ..context }),
+            false => Ok(serde_json::to_string(payload)?),
+        }
+    }
+
+    /// Retries failures that are likely to be temporary right away, the outbox retries the rest later
+    async fn post(&self, event: &str, idempotency_key: &str, body: String) -> anyhow::Result<()> {
+        let mut attempt: u32 = 0;
+        loop {
+            let mut request = self.http
+                .post(self.url.clone())
+                .headers(self.headers.clone())
+                .header(EVENT_HEADER, event)
+                .header(IDEMPOTENCY_HEADER, idempotency_key)
+                .body(body.clone());
+            if let Some(secret) = &self.secret {
+                request = request.header(SIGNATURE_HEADER, format!("sha256={}", crypto::sign(secret, body.as_bytes())));
+            }
+            // The URL may contain a secret, so it's left out of errors that end up in logs and the outbox
+            let result = request.send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| e.without_url());
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.retries && is_temporary(&e) => {
+                    sleep(Duration::from_secs(backoff(1, attempt, 30)), &self.cancellation_token).await;
+                    if self.cancellation_token.is_cancelled() {
+                        return Err(e.into());
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    async fn send_report<T: ReportView>(&self, source_domain: &str, report: &T) -> anyhow::Result<()> {
+        let event = T::KIND.as_str();
+        let payload = JsonPayload { source_domain, report };
+        let body = self.body(event, &payload, report_context(source_domain, report))?;
+        let idempotency_key = format!("{source_domain}:{event}:{}", report.report_id());
+        self.post(event, &idempotency_key, body).await
+    }
+}
+
+fn is_temporary(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.status().is_some_and(|status| status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS)
+}
+
+#[async_trait]
+impl NotifyReport for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify_post(&self, source_domain: &str, report: &PostReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, report).await
+    }
+
+    async fn notify_comment(&self, source_domain: &str, report: &CommentReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, report).await
+    }
+
+    async fn notify_private_message(&self, source_domain: &str, report: &PrivateMessageReportView) -> anyhow::Result<()> {
+        self.send_report(source_domain, report).await
+    }
+
+    async fn notify_registration_application(&self, source_domain: &str, application: &RegistrationApplicationView) -> anyhow::Result<()> {
+        let event = "registration_application";
+        let payload = JsonPayload { source_domain, report: application };
+        let body = self.body(event, &payload, registration_application_context(source_domain, application))?;
+        let application_id = stupid::extract_id(application.registration_application.id);
+        self.post(event, &format!("{source_domain}:{event}:{application_id}"), body).await
+    }
+
+    async fn notify_update(&self, source_domain: &str, update: &ReportUpdate) -> anyhow::Result<()> {
+        let event = "update";
+        let payload = JsonPayload { source_domain, report: update };
+        let body = self.body(event, &payload, update_context(source_domain, update))?;
+        // A report can reach the same state again later, so the change itself identifies the update
+        // Updates queued before the history ID was added fall back to the state
+        let change = update.history_id.map(|id| id.to_string()).unwrap_or_else(|| update.state().replace(' ', "_"));
+        let idempotency_key = format!("{source_domain}:{}:{}:update:{change}", update.kind, update.report_id);
+        self.post(event, &idempotency_key, body).await
+    }
+
+    async fn notify_federation_gaps(&self, gaps: &[FederationGap]) -> anyhow::Result<()> {
+        let event = "federation_gaps";
+        let body = self.body(event, &gaps, federation_gaps_context(gaps))?;
+        // Summaries don't have an ID, but the same summary always has the same content
+        let idempotency_key = format!("{event}:{}", crypto::hash(body.as_bytes()));
+        self.post(event, &idempotency_key, body).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::ReportKind;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// Stand-in receiver that answers one request per status and returns the requests,
+    /// `None` doesn't answer until the client gave up on the request
+    fn receiver(statuses: Vec<Option<u16>>) -> (Url, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/hook", listener.local_addr().unwrap())).unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+                requests.push(request);
+
+                match status {
+                    Some(status) => write!(stream, "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap(),
+                    // Reading until the client closes the connection
+                    None => while reader.read_line(&mut String::new()).is_ok_and(|read| read > 0) {},
+                }
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn notifier(url: Url, secret: Option<&str>, retries: u32) -> WebhookNotifier {
+        WebhookNotifier {
+            name: "webhook".to_string(),
+            http: reqwest::Client::builder().timeout(Duration::from_millis(500)).build().unwrap(),
+            url,
+            headers: HeaderMap::new(),
+            secret: secret.map(str::to_string),
+            retries,
+            templates: Templates::new(DEFAULT_TEMPLATES, &HashMap::new()).unwrap(),
+            custom_body: false,
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    fn update() -> ReportUpdate {
+        ReportUpdate {
+            kind: ReportKind::Post,
+            report_id: 42,
+            resolved: true,
+            resolver: Some("https://lemmy.world/u/mod".to_string()),
+            resolver_id: Some(7),
+            content_removed: false,
+            summary: "Resolved".to_string(),
+            url: Some("https://lemmy.world/post/1".to_string()),
+            history_id: Some(3),
+        }
+    }
+
+    fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+        let prefix = format!("{}: ", name.to_ascii_lowercase());
+        request.split("\r\n")
+            .take_while(|line| !line.is_empty())
+            .find(|line| line.to_ascii_lowercase().starts_with(&prefix))
+            .map(|line| &line[prefix.len()..])
+    }
+
+    fn body(request: &str) -> &str {
+        request.split_once("\r\n\r\n").unwrap().1
+    }
+
+    #[tokio::test]
+    async fn signed_update() {
+        let (url, server) = receiver(vec![Some(200)]);
+        notifier(url, Some("secret"), 0).notify_update("lemmy.world", &update()).await.unwrap();
+
+        let requests = server.join().unwrap();
+        let request = &requests[0];
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert_eq!(Some("update"), header(request, EVENT_HEADER));
+        assert_eq!(Some("lemmy.world:post:42:update:3"), header(request, IDEMPOTENCY_HEADER));
+        let signature = format!("sha256={}", crypto::sign("secret", body(request).as_bytes()));
+        assert_eq!(Some(signature.as_str()), header(request, SIGNATURE_HEADER));
+        // Same as the MQTT payload
+        let expected = serde_json::to_string(&JsonPayload { source_domain: "lemmy.world", report: &update() }).unwrap();
+        assert_eq!(expected, body(request));
+        assert_eq!(json!("https://lemmy.world/u/mod"), serde_json::from_str::<serde_json::Value>(body(request)).unwrap()["report"]["resolver"]);
+    }
+
+    #[tokio::test]
+    async fn unsigned_without_secret() {
+        let (url, server) = receiver(vec![Some(204)]);
+        notifier(url, None, 0).notify_federation_gaps(&[]).await.unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(None, header(&requests[0], SIGNATURE_HEADER));
+        assert_eq!("[]", body(&requests[0]));
+        let idempotency_key = format!("federation_gaps:{}", crypto::hash(b"[]"));
+        assert_eq!(Some(idempotency_key.as_str()), header(&requests[0], IDEMPOTENCY_HEADER));
+    }
+
+    #[tokio::test]
+    async fn retry_temporary_failures() {
+        let (url, server) = receiver(vec![Some(503), Some(429), None, Some(200)]);
+        notifier(url, None, 3).notify_update("lemmy.world", &update()).await.unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(4, requests.len());
+        assert!(requests.iter().all(|request| header(request, IDEMPOTENCY_HEADER) == Some("lemmy.world:post:42:update:3")));
+    }
+
+    #[tokio::test]
+    async fn other_failures_are_left_to_the_outbox() {
+        let (url, server) = receiver(vec![Some(400)]);
+        let error = notifier(url, None, 3).notify_update("lemmy.world", &update()).await.unwrap_err();
+        assert_eq!(Some(StatusCode::BAD_REQUEST), error.downcast_ref::<reqwest::Error>().and_then(|e| e.status()));
+        assert_eq!(1, server.join().unwrap().len());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::db;
+use crate::db::DbPool;
+use crate::env::OutboxCommand;
+use crate::models::federation_gap::FederationGap;
+use crate::models::outbox_notification::NewOutboxNotificationEntity;
+use crate::notify::NotifyReport;
+use crate::report::{ReportKind, ReportUpdate, ReportView};
+use crate::util::{backoff, sleep};
+use anyhow::anyhow;
+use diesel_async::AsyncPgConnection;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView, RegistrationApplicationView};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 100;
+/// Wait in seconds before the first retry, doubled after every further failure
+const RETRY_INTERVAL: u64 = 30;
+
+/// Everything notifiers can be asked to send, stored in the outbox until it's delivered
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    Post {
+        source_domain: String,
+        report: PostReportView,
+    },
+    Comment {
+        source_domain: String,
+        report: CommentReportView,
+    },
+    PrivateMessage {
+        source_domain: String,
+        report: PrivateMessageReportView,
+    },
+    RegistrationApplication {
+        source_domain: String,
+        application: RegistrationApplicationView,
+    },
+    Update {
+        source_domain: String,
+        update: ReportUpdate,
+    },
+    Copy {
+        origin_domain: String,
+        kind: ReportKind,
+        origin_report_id: i32,
+        seen_on: Vec<String>,
+    },
+    FederationGaps {
+        gaps: Vec<FederationGap>,
+    },
+}
+
+impl Notification {
+    pub fn event(&self) -> &'static str {
+        match self {
+            Notification::Post { .. } => "post",
+            Notification::Comment { .. } => "comment",
+            Notification::PrivateMessage { .. } => "private_message",
+            Notification::RegistrationApplication { .. } => "registration_application",
+            Notification::Update { .. } => "update",
+            Notification::Copy { .. } => "copy",
+            Notification::FederationGaps { .. } => "federation_gaps",
+        }
+    }
+
+    pub fn source_domain(&self) -> Option<&str> {
+        match self {
+            Notification::Post { source_domain, .. }
+            | Notification::Comment { source_domain, .. }
+            | Notification::PrivateMessage { source_domain, .. }
+            | Notification::RegistrationApplication { source_domain, .. }
+            | Notification::Update { source_domain, .. } => Some(source_domain),
+            Notification::Copy { origin_domain, .. } => Some(origin_domain),
+            Notification::FederationGaps { .. } => None,
+        }
+    }
+
+    /// Identifies the report the notification is about, notifications of the same report are delivered in the order they were queued
+    pub fn report(&self) -> Option<String> {
+        match self {
+            Notification::Post { source_domain, report } => Some(report_reference(source_domain, ReportKind::Post, report.report_id())),
+            Notification::Comment { source_domain, report } => Some(report_reference(source_domain, ReportKind::Comment, report.report_id())),
+            Notification::PrivateMessage { source_domain, report } => Some(report_reference(source_domain, ReportKind::PrivateMessage, report.report_id())),
+            Notification::Update { source_domain, update } => Some(report_reference(source_domain, update.kind, update.report_id)),
+            Notification::Copy { origin_domain, kind, origin_report_id, .. } => Some(report_reference(origin_domain, *kind, *origin_report_id)),
+            Notification::RegistrationApplication { .. } | Notification::FederationGaps { .. } => None,
+        }
+    }
+
+    pub async fn deliver(&self, notifier: &dyn NotifyReport) -> anyhow::Result<()> {
+        match self {
+            Notification::Post { source_domain, report } => notifier.notify_post(source_domain, report).await,
+            Notification::Comment { source_domain, report } => notifier.notify_comment(source_domain, report).await,
+            Notification::PrivateMessage { source_domain, report } => notifier.notify_private_message(source_domain, report).await,
+            Notification::RegistrationApplication { source_domain, application } => notifier.notify_registration_application(source_domain, application).await,
+            Notification::Update { source_domain, update } => notifier.notify_update(source_domain, update).await,
+            Notification::Copy { origin_domain, kind, origin_report_id, seen_on } => notifier.notify_copy(origin_domain, *kind, *origin_report_id, seen_on).await,
+            Notification::FederationGaps { gaps } => notifier.notify_federation_gaps(gaps).await,
+        }
+    }
+}
+
+fn report_reference(domain: &str, kind: ReportKind, report_id: i32) -> String {
+    format!("{domain}/{kind}/{report_id}")
+}
+
+/// Queues the notification for the given notifiers, should be called in the same transaction that stores the report
+pub async fn enqueue(db_conn: &mut AsyncPgConnection, notifier_names: &[String], notification: &Notification) -> anyhow::Result<()> {
+    let payload = serde_json::to_value(notification)?;
+    let entries = notifier_names
+        .iter()
+        .map(|name| NewOutboxNotificationEntity {
+            notifier: name.clone(),
+            event: notification.event().to_string(),
+            source_domain: notification.source_domain().map(str::to_string),
+            payload: payload.clone(),
+            report: notification.report(),
+        })
+        .collect::<Vec<_>>();
+    db::insert_outbox_notifications(db_conn, &entries).await
+}
+
+#[derive(Clone)]
+pub struct DeliveryOptions {
+    pub max_attempts: i32,
+    pub max_backoff: u64,
+    /// Maximum time a single notification may take
+    pub timeout: Duration,
+}
+
+/// Delivery counters of a notifier, logged on failures and recovery
+#[derive(Default)]
+struct NotifierHealth {
+    delivered: u64,
+    failed: u64,
+    consecutive_failures: u64,
+}
+
+/// Delivers queued notifications until cancelled, every notifier has its own worker so a failing one can't hold up the others
+pub async fn deliver_notifications(token: CancellationToken, db_pool: DbPool, notifiers: Arc<Vec<Box<dyn NotifyReport>>>, options: DeliveryOptions) {
+    let names = notifiers.iter().map(|notifier| notifier.name().to_string()).collect::<Vec<_>>();
+    match db_pool.get().await {
+        Ok(mut db_conn) => {
+            if let Err(err) = db::kill_unknown_outbox_notifications(&mut db_conn, &names).await {
+                println!("Failed to clean up notifications of removed notifiers: {err}");
+            }
+        }
+        Err(err) => println!("Failed to clean up notifications of removed notifiers: {err}"),
+    }
+
+    let workers = (0..notifiers.len())
+        .map(|index| tokio::spawn(deliver_notifier_notifications(token.clone(), db_pool.clone(), notifiers.clone(), index, options.clone())))
+        .collect::<Vec<_>>();
+    for worker in workers {
+        if let Err(e) = worker.await {
+            eprintln!("Error during notifier worker shutdown: {}", e);
+        }
+    }
+}
+
+async fn deliver_notifier_notifications(token: CancellationToken, db_pool: DbPool, notifiers: Arc<Vec<Box<dyn NotifyReport>>>, index: usize, options: DeliveryOptions) {
+    let notifier = notifiers[index].as_ref();
+    let mut health = NotifierHealth::default();
+    while !token.is_cancelled() {
+        if let Err(err) = deliver_due_notifications(&db_pool, notifier, &options, &mut health).await {
+            println!("Failed to deliver notifications via {}: {err}", notifier.name());
+        }
+        sleep(POLL_INTERVAL, &token).await;
+    }
+}
+
+async fn deliver_due_notifications(db_pool: &DbPool, notifier: &dyn NotifyReport, options: &DeliveryOptions, health: &mut NotifierHealth) -> anyhow::Result<()> {
+    let mut db_conn = db_pool.get().await?;
+    let due = db::get_due_outbox_notifications(&mut db_conn, notifier.name(), BATCH_SIZE).await?;
+    for entry in due {
+        let result = match serde_json::from_value::<Notification>(entry.payload) {
+            Ok(notification) => tokio::time::timeout(options.timeout, notification.deliver(notifier))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("Timed out after {}s", options.timeout.as_secs()))),
+            Err(err) => Err(anyhow!("Invalid payload: {err}")),
+        };
+        match result {
+            Ok(_) => {
+                if health.consecutive_failures > 0 {
+                    println!("{} recovered after {} failed attempts", notifier.name(), health.consecutive_failures);
+                }
+                health.delivered += 1;
+                health.consecutive_failures = 0;
+                db::delete_outbox_notification(&mut db_conn, entry.id).await?;
+            }
+            Err(err) => {
+                health.failed += 1;
+                health.consecutive_failures += 1;
+                let attempts = entry.attempts + 1;
+                let retry_in = (attempts < options.max_attempts).then(|| backoff(RETRY_INTERVAL, (attempts - 1) as u32, options.max_backoff));
+                let counters = format!("{} consecutive failures, {} failed and {} delivered in total", health.consecutive_failures, health.failed, health.delivered);
+                match retry_in {
+                    Some(retry_in) => println!("Failed to send {} notification {} via {}: {err}, retrying in {retry_in}s ({counters})", entry.event, entry.id, notifier.name()),
+                    None => eprintln!("Failed to send {} notification {} via {} after {attempts} attempts: {err} ({counters})", entry.event, entry.id, notifier.name()),
+                }
+                db::fail_outbox_notification(&mut db_conn, entry.id, &err.to_string(), retry_in).await?;
+                // The notifier is most likely still down, its other notifications wait for the next poll
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn run_command(db_conn: &mut AsyncPgConnection, command: &OutboxCommand) -> anyhow::Result<()> {
+    match command {
+        OutboxCommand::List { dead } => {
+            let status = dead.then_some(db::OUTBOX_DEAD);
+            let notifications = db::get_outbox_notifications(db_conn, status).await?;
+            for notification in &notifications {
+                println!(
+                    "{id}\t{status}\t{notifier}\t{event}\t{domain}\t{attempts} attempts\t{error}",
+                    id = notification.id,
+                    status = notification.status,
+                    notifier = notification.notifier,
+                    event = notification.event,
+                    domain = notification.source_domain.as_deref().unwrap_or("-"),
+                    attempts = notification.attempts,
+                    error = notification.last_error.as_deref().unwrap_or_default(),
+                );
+            }
+            println!("{count} notifications", count = notifications.len());
+        }
+        OutboxCommand::Retry { ids } => {
+            let count = db::retry_dead_outbox_notifications(db_conn, ids).await?;
+            println!("{count} notifications will be retried");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_and_copy_reference_the_report() {
+        let update = ReportUpdate {
+            kind: ReportKind::Comment,
+            report_id: 12,
+            resolved: true,
+            resolver: None,
+            resolver_id: None,
+            content_removed: false,
+            summary: "Comment on post".to_string(),
+            url: None,
+            history_id: Some(3),
+        };
+        let update = Notification::Update { source_domain: "lemmy.world".to_string(), update };
+        let copy = Notification::Copy { origin_domain: "lemmy.world".to_string(), kind: ReportKind::Comment, origin_report_id: 12, seen_on: vec![] };
+        assert_eq!(Some("lemmy.world/comment/12".to_string()), update.report());
+        assert_eq!(update.report(), copy.report());
+        assert_eq!(None, Notification::FederationGaps { gaps: vec![] }.report());
+    }
+}
@@ -0,0 +1,373 @@
+use crate::stupid;
+use lemmy_client::lemmy_api_common::lemmy_db_schema::source::community::Community;
+use lemmy_client::lemmy_api_common::lemmy_db_schema::source::person::Person;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::{CommentReportView, PostReportView, PrivateMessageReportView};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportKind {
+    Post,
+    Comment,
+    PrivateMessage,
+}
+
+impl ReportKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportKind::Post => "post",
+            ReportKind::Comment => "comment",
+            ReportKind::PrivateMessage => "private_message",
+        }
+    }
+}
+
+impl Display for ReportKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Common accessors for the different report views returned by Lemmy
+pub trait ReportView: Clone + Serialize + DeserializeOwned + Send + Sync {
+    const KIND: ReportKind;
+
+    fn report_id(&self) -> i32;
+    fn resolved(&self) -> bool;
+    fn resolver(&self) -> Option<&Person>;
+    /// Whether the reported content was removed by a moderator
+    fn content_removed(&self) -> bool;
+    /// Short human-readable description of the reported content
+    fn summary(&self) -> String;
+    fn content_url(&self, domain: &str) -> Option<String>;
+    /// Same for every copy of the report, regardless of the instance it was federated to
+    fn federation_key(&self) -> String;
+    fn community(&self) -> Option<&Community>;
+    /// Post the reported content belongs to
+    fn post_id(&self) -> Option<i32>;
+    fn reason(&self) -> &str;
+    /// Creator of the reported content, not the reporter
+    fn content_creator(&self) -> &Person;
+}
+
+impl ReportView for PostReportView {
+    const KIND: ReportKind = ReportKind::Post;
+
+    fn report_id(&self) -> i32 {
+        stupid::extract_id(self.post_report.id)
+    }
+
+    fn resolved(&self) -> bool {
+        self.post_report.resolved
+    }
+
+    fn resolver(&self) -> Option<&Person> {
+        self.resolver.as_ref()
+    }
+
+    fn content_removed(&self) -> bool {
+        self.post.removed
+    }
+
+    fn summary(&self) -> String {
+        self.post.name.clone()
+    }
+
+    fn content_url(&self, domain: &str) -> Option<String> {
+        Some(format!("https://{domain}/post/{post_id}", post_id = self.post.id))
+    }
+
+    fn federation_key(&self) -> String {
+        format!("{} {}", self.post.ap_id, self.creator.actor_id)
+    }
+
+    fn community(&self) -> Option<&Community> {
+        Some(&self.community)
+    }
+
+    fn post_id(&self) -> Option<i32> {
+        Some(stupid::extract_id(self.post.id))
+    }
+
+    fn reason(&self) -> &str {
+        &self.post_report.reason
+    }
+
+    fn content_creator(&self) -> &Person {
+        &self.post_creator
+    }
+}
+
+impl ReportView for CommentReportView {
+    const KIND: ReportKind = ReportKind::Comment;
+
+    fn report_id(&self) -> i32 {
+        stupid::extract_id(self.comment_report.id)
+    }
+
+    fn resolved(&self) -> bool {
+        self.comment_report.resolved
+    }
+
+    fn resolver(&self) -> Option<&Person> {
+        self.resolver.as_ref()
+    }
+
+    fn content_removed(&self) -> bool {
+        self.comment.removed
+    }
+
+    fn summary(&self) -> String {
+        format!("Comment on {post_name}", post_name = self.post.name)
+    }
+
+    fn content_url(&self, domain: &str) -> Option<String> {
+        Some(format!("https://{domain}/post/{post_id}/{comment_id}", post_id = self.post.id, comment_id = self.comment.id))
+    }
+
+    fn federation_key(&self) -> String {
+        format!("{} {}", self.comment.ap_id, self.creator.actor_id)
+    }
+
+    fn community(&self) -> Option<&Community> {
+        Some(&self.community)
+    }
+
+    fn post_id(&self) -> Option<i32> {
+        Some(stupid::extract_id(self.post.id))
+    }
+
+    fn reason(&self) -> &str {
+        &self.comment_report.reason
+    }
+
+    fn content_creator(&self) -> &Person {
+        &self.comment_creator
+    }
+}
+
+impl ReportView for PrivateMessageReportView {
+    const KIND: ReportKind = ReportKind::PrivateMessage;
+
+    fn report_id(&self) -> i32 {
+        stupid::extract_id(self.private_message_report.id)
+    }
+
+    fn resolved(&self) -> bool {
+        self.private_message_report.resolved
+    }
+
+    fn resolver(&self) -> Option<&Person> {
+        self.resolver.as_ref()
+    }
+
+    fn content_removed(&self) -> bool {
+        // Private messages can only be deleted by their author
+        false
+    }
+
+    fn summary(&self) -> String {
+        // The content is left out on purpose, it might have to be redacted
+        format!("Private message from {creator}", creator = self.private_message_creator.name)
+    }
+
+    fn content_url(&self, _domain: &str) -> Option<String> {
+        None
+    }
+
+    fn federation_key(&self) -> String {
+        format!("{} {}", self.private_message.ap_id, self.creator.actor_id)
+    }
+
+    fn community(&self) -> Option<&Community> {
+        None
+    }
+
+    fn post_id(&self) -> Option<i32> {
+        None
+    }
+
+    fn reason(&self) -> &str {
+        &self.private_message_report.reason
+    }
+
+    fn content_creator(&self) -> &Person {
+        &self.private_message_creator
+    }
+}
+
+/// A change in the state of a report which was already known
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReportUpdate {
+    pub kind: ReportKind,
+    pub report_id: i32,
+    pub resolved: bool,
+    /// Actor ID of the person who resolved the report
+    pub resolver: Option<String>,
+    #[serde(skip)]
+    pub resolver_id: Option<i32>,
+    pub content_removed: bool,
+    pub summary: String,
+    pub url: Option<String>,
+    /// ID of the change in `report_history`, identifies the update when the same state is reached again later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_id: Option<i64>,
+}
+
+impl ReportUpdate {
+    pub fn from_view<T: ReportView>(domain: &str, view: &T) -> Self {
+        let resolver = match view.resolved() {
+            true => view.resolver(),
+            false => None,
+        };
+        ReportUpdate {
+            kind: T::KIND,
+            report_id: view.report_id(),
+            resolved: view.resolved(),
+            resolver: resolver.map(|person| person.actor_id.to_string()),
+            resolver_id: resolver.map(|person| stupid::extract_id(person.id)),
+            content_removed: view.content_removed(),
+            summary: view.summary(),
+            url: view.content_url(domain),
+            history_id: None,
+        }
+    }
+
+    /// Describes the new state, e.g. to be used in titles
+    pub fn state(&self) -> &'static str {
+        match (self.resolved, self.content_removed) {
+            (true, _) => "resolved",
+            (false, true) => "content removed",
+            (false, false) => "reopened",
+        }
+    }
+}
+
+/// The first seen copy of a report which federated to multiple instances
+pub struct ReportOrigin {
+    pub domain: String,
+    pub report_id: i32,
+    /// Every instance the report was seen on, including the origin
+    pub seen_on: Vec<String>,
+}
+
+/// Compares the fetched reports with the stored data and returns those of which the state changed
+pub fn detect_updates<T: ReportView>(domain: &str, views: &[T], known: &HashMap<i32, serde_json::Value>) -> Vec<(T, ReportUpdate)> {
+    views
+        .iter()
+        .filter_map(|view| {
+            let stored = known.get(&view.report_id())?;
+            let stored_view = serde_json::from_value::<T>(stored.clone()).ok()?;
+            if stored_view.resolved() == view.resolved() && stored_view.content_removed() == view.content_removed() {
+                return None;
+            }
+            Some((view.clone(), ReportUpdate::from_view(domain, view)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the accessors used to detect updates are implemented
+    #[derive(Clone, Serialize, Deserialize)]
+    struct TestReport {
+        id: i32,
+        resolved: bool,
+        removed: bool,
+    }
+
+    impl ReportView for TestReport {
+        const KIND: ReportKind = ReportKind::Post;
+
+        fn report_id(&self) -> i32 {
+            self.id
+        }
+
+        fn resolved(&self) -> bool {
+            self.resolved
+        }
+
+        fn resolver(&self) -> Option<&Person> {
+            None
+        }
+
+        fn content_removed(&self) -> bool {
+            self.removed
+        }
+
+        fn summary(&self) -> String {
+            format!("Post {}", self.id)
+        }
+
+        fn content_url(&self, domain: &str) -> Option<String> {
+            Some(format!("https://{domain}/post/{}", self.id))
+        }
+
+        fn federation_key(&self) -> String {
+            self.id.to_string()
+        }
+
+        fn community(&self) -> Option<&Community> {
+            None
+        }
+
+        fn post_id(&self) -> Option<i32> {
+            Some(self.id)
+        }
+
+        fn reason(&self) -> &str {
+            ""
+        }
+
+        fn content_creator(&self) -> &Person {
+            unimplemented!()
+        }
+    }
+
+    fn report(id: i32, resolved: bool, removed: bool) -> TestReport {
+        TestReport { id, resolved, removed }
+    }
+
+    fn known(reports: &[TestReport]) -> HashMap<i32, serde_json::Value> {
+        reports.iter().map(|r| (r.id, serde_json::to_value(r).unwrap())).collect()
+    }
+
+    #[test]
+    fn resolved_report() {
+        let updates = detect_updates("lemmy.world", &[report(1, true, false)], &known(&[report(1, false, false)]));
+        assert_eq!(1, updates.len());
+        let (_, update) = &updates[0];
+        assert_eq!(1, update.report_id);
+        assert!(update.resolved);
+        assert_eq!("resolved", update.state());
+        assert_eq!(Some("https://lemmy.world/post/1"), update.url.as_deref());
+    }
+
+    #[test]
+    fn reopened_report() {
+        let updates = detect_updates("lemmy.world", &[report(1, false, false)], &known(&[report(1, true, false)]));
+        assert_eq!(1, updates.len());
+        assert!(!updates[0].1.resolved);
+        assert_eq!("reopened", updates[0].1.state());
+    }
+
+    #[test]
+    fn content_removed() {
+        let updates = detect_updates("lemmy.world", &[report(1, false, true)], &known(&[report(1, false, false)]));
+        assert_eq!(1, updates.len());
+        assert!(updates[0].1.content_removed);
+        assert_eq!("content removed", updates[0].1.state());
+    }
+
+    #[test]
+    fn unchanged_and_new_reports() {
+        let views = [report(1, false, false), report(2, true, true), report(3, false, false)];
+        let updates = detect_updates("lemmy.world", &views, &known(&[report(1, false, false), report(2, true, true)]));
+        assert!(updates.is_empty());
+    }
+}
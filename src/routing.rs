@@ -0,0 +1,330 @@
+use crate::outbox::Notification;
+use crate::report::{ReportKind, ReportView};
+use anyhow::anyhow;
+use lemmy_client::lemmy_api_common::lemmy_db_schema::source::community::Community;
+use lemmy_client::lemmy_api_common::lemmy_db_schema::source::person::Person;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::RegistrationApplicationView;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+pub const REGISTRATION_APPLICATION_KIND: &str = "registration_application";
+pub const FEDERATION_GAPS_KIND: &str = "federation_gaps";
+/// Everything `kinds` of a route can contain
+const ROUTE_KINDS: &[&str] = &["post", "comment", "private_message", REGISTRATION_APPLICATION_KIND, FEDERATION_GAPS_KIND];
+
+/// Sends matching reports to the listed notifiers, empty criteria match everything
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    pub notifiers: Vec<String>,
+    /// Instances the report was seen on
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Community names, `name@instance` or actor IDs
+    #[serde(default)]
+    pub communities: Vec<String>,
+    /// `post`, `comment`, `private_message`, `registration_application` or `federation_gaps`
+    #[serde(default)]
+    pub kinds: Vec<String>,
+    /// Matches when the reason contains any of the keywords, ignoring case
+    #[serde(default)]
+    pub reason_keywords: Vec<String>,
+    /// Names, `name@instance` or actor IDs of the creators of the reported content
+    #[serde(default)]
+    pub creators: Vec<String>,
+    pub creator_bot: Option<bool>,
+    pub creator_banned: Option<bool>,
+    pub creator_local: Option<bool>,
+}
+
+/// Everything routes can match on
+pub struct RouteTarget<'a> {
+    pub domain: Option<&'a str>,
+    pub kind: &'a str,
+    pub community: Option<&'a Community>,
+    pub reason: Option<&'a str>,
+    pub creator: Option<&'a Person>,
+}
+
+impl<'a> RouteTarget<'a> {
+    pub fn from_view<T: ReportView>(domain: &'a str, view: &'a T) -> Self {
+        RouteTarget {
+            domain: Some(domain),
+            kind: T::KIND.as_str(),
+            community: view.community(),
+            reason: Some(view.reason()),
+            creator: Some(view.content_creator()),
+        }
+    }
+
+    pub fn from_registration_application(domain: &'a str, application: &'a RegistrationApplicationView) -> Self {
+        RouteTarget {
+            domain: Some(domain),
+            kind: REGISTRATION_APPLICATION_KIND,
+            community: None,
+            reason: Some(&application.registration_application.answer),
+            creator: Some(&application.creator),
+        }
+    }
+
+    pub fn federation_gaps() -> Self {
+        RouteTarget {
+            domain: None,
+            kind: FEDERATION_GAPS_KIND,
+            community: None,
+            reason: None,
+            creator: None,
+        }
+    }
+
+    /// Updates and copies are routed by the report they belong to, so they don't have a target of their own
+    pub fn from_notification(notification: &'a Notification) -> Option<Self> {
+        match notification {
+            Notification::Post { source_domain, report } => Some(RouteTarget::from_view(source_domain, report)),
+            Notification::Comment { source_domain, report } => Some(RouteTarget::from_view(source_domain, report)),
+            Notification::PrivateMessage { source_domain, report } => Some(RouteTarget::from_view(source_domain, report)),
+            Notification::RegistrationApplication { source_domain, application } => Some(RouteTarget::from_registration_application(source_domain, application)),
+            Notification::FederationGaps { .. } => Some(RouteTarget::federation_gaps()),
+            Notification::Update { .. } | Notification::Copy { .. } => None,
+        }
+    }
+}
+
+/// `name@instance`, which is how communities and users are usually written
+pub fn qualified_name(name: &str, actor_id: &str) -> Option<String> {
+    let host = Url::parse(actor_id).ok()?.host_str()?.to_string();
+    Some(format!("{name}@{host}"))
+}
+
+fn matches_actor(patterns: &[String], name: &str, actor_id: &str) -> bool {
+    let qualified = qualified_name(name, actor_id);
+    patterns.iter().any(|pattern| {
+        pattern == name
+            || pattern.trim_end_matches('/') == actor_id.trim_end_matches('/')
+            || qualified.as_deref() == Some(pattern.as_str())
+    })
+}
+
+impl RouteConfig {
+    fn matches(&self, target: &RouteTarget) -> bool {
+        if !self.domains.is_empty() && !target.domain.is_some_and(|domain| self.domains.iter().any(|d| d == domain)) {
+            return false;
+        }
+        if !self.kinds.is_empty() && !self.kinds.iter().any(|kind| kind == target.kind) {
+            return false;
+        }
+        if !self.communities.is_empty() {
+            let Some(community) = target.community else {
+                return false;
+            };
+            if !matches_actor(&self.communities, &community.name, &community.actor_id.to_string()) {
+                return false;
+            }
+        }
+        if !self.reason_keywords.is_empty() {
+            let reason = target.reason.unwrap_or_default().to_lowercase();
+            if !self.reason_keywords.iter().any(|keyword| reason.contains(&keyword.to_lowercase())) {
+                return false;
+            }
+        }
+        let creator_criteria = !self.creators.is_empty() || self.creator_bot.is_some() || self.creator_banned.is_some() || self.creator_local.is_some();
+        if creator_criteria {
+            let Some(creator) = target.creator else {
+                return false;
+            };
+            if !self.creators.is_empty() && !matches_actor(&self.creators, &creator.name, &creator.actor_id.to_string()) {
+                return false;
+            }
+            if self.creator_bot.is_some_and(|bot| bot != creator.bot_account)
+                || self.creator_banned.is_some_and(|banned| banned != creator.banned)
+                || self.creator_local.is_some_and(|local| local != creator.local) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Decides which notifiers receive a notification.
+/// Notifiers that aren't part of any route receive everything, the others only what matches one of their routes.
+pub struct Router {
+    notifiers: Vec<String>,
+    routes: Vec<RouteConfig>,
+}
+
+impl Router {
+    pub fn new(notifiers: Vec<String>, routes: Vec<RouteConfig>) -> anyhow::Result<Self> {
+        for route in &routes {
+            if let Some(unknown) = route.notifiers.iter().find(|name| !notifiers.contains(name)) {
+                return Err(anyhow!("Route refers to unknown notifier '{unknown}'"));
+            }
+            if let Some(unknown) = route.kinds.iter().find(|kind| !ROUTE_KINDS.contains(&kind.as_str())) {
+                return Err(anyhow!("Route refers to unknown kind '{unknown}', known kinds are {}", ROUTE_KINDS.join(", ")));
+            }
+        }
+        Ok(Router { notifiers, routes })
+    }
+
+    /// Indexes of the routes that match the target, in the order they were configured
+    pub fn matching_routes(&self, target: &RouteTarget) -> Vec<usize> {
+        self.routes
+            .iter()
+            .enumerate()
+            .filter(|(_, route)| route.matches(target))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    pub fn route(&self, target: &RouteTarget) -> Vec<String> {
+        let routed = self.routes
+            .iter()
+            .flat_map(|route| &route.notifiers)
+            .collect::<HashSet<_>>();
+        let matched = self.matching_routes(target)
+            .into_iter()
+            .flat_map(|index| &self.routes[index].notifiers)
+            .collect::<HashSet<_>>();
+        self.notifiers
+            .iter()
+            .filter(|name| !routed.contains(name) || matched.contains(name))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Prints where a report would be sent, the file may contain an outbox payload or the data stored for a report
+pub fn dry_run(router: &Router, path: &Path, domain: Option<&str>) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let value: serde_json::Value = serde_json::from_str(&content)?;
+    let notification = match value.get("event") {
+        Some(_) => serde_json::from_value::<Notification>(value)?,
+        None => {
+            let source_domain = domain
+                .ok_or_else(|| anyhow!("--domain is required for stored report data"))?
+                .to_string();
+            notification_from_stored_data(source_domain, value)?
+        }
+    };
+    let target = RouteTarget::from_notification(&notification)
+        .ok_or_else(|| anyhow!("{} notifications are routed like the report they belong to", notification.event()))?;
+
+    let matching_routes = router.matching_routes(&target);
+    match matching_routes.is_empty() {
+        true => println!("Matching routes: none"),
+        false => println!("Matching routes: {}", matching_routes.iter().map(|index| format!("#{}", index + 1)).collect::<Vec<_>>().join(", ")),
+    }
+    println!("Notifiers: {}", router.route(&target).join(", "));
+    Ok(())
+}
+
+fn notification_from_stored_data(source_domain: String, value: serde_json::Value) -> anyhow::Result<Notification> {
+    if value.get("post_report").is_some() {
+        return Ok(Notification::Post { source_domain, report: serde_json::from_value(value)? });
+    }
+    if value.get("comment_report").is_some() {
+        return Ok(Notification::Comment { source_domain, report: serde_json::from_value(value)? });
+    }
+    if value.get("private_message_report").is_some() {
+        return Ok(Notification::PrivateMessage { source_domain, report: serde_json::from_value(value)? });
+    }
+    if value.get("registration_application").is_some() {
+        return Ok(Notification::RegistrationApplication { source_domain, application: serde_json::from_value(value)? });
+    }
+    Err(anyhow!("Unknown report data, expected a {}, {}, {} report or a registration application", ReportKind::Post, ReportKind::Comment, ReportKind::PrivateMessage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(notifiers: &[&str]) -> RouteConfig {
+        RouteConfig {
+            notifiers: notifiers.iter().map(|n| n.to_string()).collect(),
+            domains: vec![],
+            communities: vec![],
+            kinds: vec![],
+            reason_keywords: vec![],
+            creators: vec![],
+            creator_bot: None,
+            creator_banned: None,
+            creator_local: None,
+        }
+    }
+
+    fn target<'a>(domain: &'a str, kind: &'a str, reason: &'a str) -> RouteTarget<'a> {
+        RouteTarget {
+            domain: Some(domain),
+            kind,
+            community: None,
+            reason: Some(reason),
+            creator: None,
+        }
+    }
+
+    fn router(routes: Vec<RouteConfig>) -> Router {
+        let notifiers = ["console", "mods", "admins"].iter().map(|n| n.to_string()).collect();
+        Router::new(notifiers, routes).unwrap()
+    }
+
+    #[test]
+    fn unrouted_notifiers_receive_everything() {
+        let router = router(vec![]);
+        assert_eq!(vec!["console", "mods", "admins"], router.route(&target("a.com", "post", "Spam")));
+    }
+
+    #[test]
+    fn routes_by_domain_and_kind() {
+        let router = router(vec![
+            RouteConfig { domains: vec!["a.com".to_string()], ..route(&["mods"]) },
+            RouteConfig { kinds: vec!["private_message".to_string()], ..route(&["admins"]) },
+        ]);
+        assert_eq!(vec!["console", "mods"], router.route(&target("a.com", "post", "Spam")));
+        assert_eq!(vec!["console", "admins"], router.route(&target("b.com", "private_message", "Spam")));
+        assert_eq!(vec!["console"], router.route(&target("b.com", "comment", "Spam")));
+    }
+
+    #[test]
+    fn routes_by_reason_keywords() {
+        let router = router(vec![
+            RouteConfig { reason_keywords: vec!["csam".to_string(), "illegal".to_string()], ..route(&["admins"]) },
+        ]);
+        assert_eq!(vec![0], router.matching_routes(&target("a.com", "post", "Possibly ILLEGAL content")));
+        assert!(router.matching_routes(&target("a.com", "post", "Rule 1")).is_empty());
+    }
+
+    #[test]
+    fn community_criteria_need_a_community() {
+        let router = router(vec![
+            RouteConfig { communities: vec!["technology".to_string()], ..route(&["mods"]) },
+        ]);
+        assert_eq!(vec!["console", "admins"], router.route(&target("a.com", "post", "Spam")));
+    }
+
+    #[test]
+    fn reject_unknown_notifiers() {
+        let notifiers = vec!["console".to_string()];
+        assert!(Router::new(notifiers, vec![route(&["mods"])]).is_err());
+    }
+
+    #[test]
+    fn reject_unknown_kinds() {
+        let notifiers = vec!["mods".to_string()];
+        let routes = vec![RouteConfig { kinds: vec!["post".to_string(), "posts".to_string()], ..route(&["mods"]) }];
+        assert_eq!(
+            "Route refers to unknown kind 'posts', known kinds are post, comment, private_message, registration_application, federation_gaps",
+            Router::new(notifiers, routes).err().unwrap().to_string(),
+        );
+        let kinds = [ReportKind::Post, ReportKind::Comment, ReportKind::PrivateMessage].map(|kind| kind.as_str().to_string());
+        assert!(Router::new(vec!["mods".to_string()], vec![RouteConfig { kinds: kinds.to_vec(), ..route(&["mods"]) }]).is_ok());
+    }
+
+    #[test]
+    fn qualified_names() {
+        assert_eq!(Some("technology@lemmy.world".to_string()), qualified_name("technology", "https://lemmy.world/c/technology"));
+        assert!(matches_actor(&["technology@lemmy.world".to_string()], "technology", "https://lemmy.world/c/technology"));
+        assert!(matches_actor(&["https://lemmy.world/c/technology/".to_string()], "technology", "https://lemmy.world/c/technology"));
+        assert!(!matches_actor(&["technology@beehaw.org".to_string()], "technology", "https://lemmy.world/c/technology"));
+    }
+}
@@ -3,6 +3,27 @@ diesel::table! {
         domain -> Text,
         username -> Text,
         password -> Text,
+        page_size -> Nullable<Int4>,
+        totp_secret -> Nullable<Text>,
+        interval_seconds -> Nullable<Int4>,
+        timeout_seconds -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    credential_tokens (domain, username) {
+        domain -> Text,
+        username -> Text,
+        jwt -> Text,
+    }
+}
+
+diesel::table! {
+    credential_roles (domain, username) {
+        domain -> Text,
+        username -> Text,
+        is_admin -> Bool,
+        communities -> Array<Text>,
     }
 }
 
@@ -11,6 +32,9 @@ diesel::table! {
         domain -> Text,
         id -> Int4,
         data -> Jsonb,
+        report_key -> Nullable<Text>,
+        first_seen -> Timestamptz,
+        gone -> Bool,
     }
 }
 
@@ -19,5 +43,100 @@ diesel::table! {
         domain -> Text,
         id -> Int4,
         data -> Jsonb,
+        report_key -> Nullable<Text>,
+        first_seen -> Timestamptz,
+        gone -> Bool,
+    }
+}
+
+diesel::table! {
+    private_message_reports (domain, id) {
+        domain -> Text,
+        id -> Int4,
+        data -> Jsonb,
+        report_key -> Nullable<Text>,
+        first_seen -> Timestamptz,
+        gone -> Bool,
+    }
+}
+
+diesel::table! {
+    registration_applications (domain, id) {
+        domain -> Text,
+        id -> Int4,
+        data -> Jsonb,
+    }
+}
+
+diesel::table! {
+    report_history (id) {
+        id -> Int8,
+        domain -> Text,
+        kind -> Text,
+        report_id -> Int4,
+        resolved -> Bool,
+        resolver_id -> Nullable<Int4>,
+        changed_at -> Timestamptz,
+        content_removed -> Bool,
+    }
+}
+
+diesel::table! {
+    discord_messages (domain, kind, report_id, webhook_id) {
+        domain -> Text,
+        kind -> Text,
+        report_id -> Int4,
+        webhook_id -> Text,
+        message_id -> Text,
+    }
+}
+
+diesel::table! {
+    email_digest_entries (id) {
+        id -> Int8,
+        notifier -> Text,
+        subject -> Text,
+        text -> Text,
+        html -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    matrix_messages (domain, kind, report_id, room_id) {
+        domain -> Text,
+        kind -> Text,
+        report_id -> Int4,
+        room_id -> Text,
+        post_id -> Nullable<Int4>,
+        event_id -> Text,
+        thread_root -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    notification_outbox (id) {
+        id -> Int8,
+        notifier -> Text,
+        event -> Text,
+        source_domain -> Nullable<Text>,
+        payload -> Jsonb,
+        status -> Text,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamptz,
+        created_at -> Timestamptz,
+        report -> Nullable<Text>,
     }
-}
\ No newline at end of file
+}
+
+diesel::table! {
+    instance_polls (domain, username) {
+        domain -> Text,
+        username -> Text,
+        last_poll -> Timestamptz,
+        last_success -> Nullable<Timestamptz>,
+        logged_in -> Bool,
+        last_error -> Nullable<Text>,
+    }
+}
@@ -1,37 +1,45 @@
-use lemmy_client::lemmy_api_common::lemmy_db_schema::newtypes::{CommentReportId, PostReportId};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 // The integer value of the newtype is not exposed
 // Diesel can work with newtypes, but that feature seems to be unavailable through the crate
 // I can't guarantee the memory layout (requires #[repr(transparent)]) so transmutate doesn't work
 // But this works and I'm not interested in finding a better method after wasting 30 minutes already
 
-pub fn extract_post_report_id(id: PostReportId) -> i32 {
+/// Works for every newtype ID, as they serialize to their integer value
+pub fn extract_id<T: Serialize>(id: T) -> i32 {
     let json = serde_json::to_string(&id).expect("Failed to convert to JSON");
-    let post_report_id: i32 = json.parse().expect("Failed to convert to JSON");
-    post_report_id
+    json.parse().expect("Failed to convert to JSON")
 }
 
-pub fn extract_comment_report_id(id: CommentReportId) -> i32 {
-    let json = serde_json::to_string(&id).expect("Failed to convert to JSON");
-    let comment_report_id: i32 = json.parse().expect("Failed to convert to JSON");
-    comment_report_id
+/// The other way around, the newtypes can't be constructed from an integer either
+pub fn newtype_id<T: DeserializeOwned>(id: i32) -> T {
+    serde_json::from_value(serde_json::Value::from(id)).expect("Failed to convert from JSON")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lemmy_client::lemmy_api_common::lemmy_db_schema::newtypes::{CommentReportId, PostReportId};
 
     #[test]
     fn post_id() {
         let id = PostReportId::default();
-        let int = extract_post_report_id(id);
+        let int = extract_id(id);
         assert_eq!(0, int);
     }
 
     #[test]
     fn comment_id() {
         let id = CommentReportId::default();
-        let int = extract_comment_report_id(id);
+        let int = extract_id(id);
         assert_eq!(0, int);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn newtype_round_trip() {
+        let id: PostReportId = newtype_id(42);
+        assert_eq!(42, extract_id(id));
+    }
+
+}
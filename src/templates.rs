@@ -0,0 +1,187 @@
+use crate::format::{escape_markdown, truncate_utf16};
+use crate::models::federation_gap::FederationGap;
+use crate::notify::describe_federation_gap;
+use crate::report::{ReportUpdate, ReportView};
+use anyhow::anyhow;
+use lemmy_client::lemmy_api_common::lemmy_db_views::structs::RegistrationApplicationView;
+use minijinja::{context, escape_formatter, Environment, Value};
+use reqwest::Url;
+use std::collections::HashMap;
+
+/// Message templates of a notifier, the defaults of the notifier type with the overrides from its config
+pub struct Templates {
+    env: Environment<'static>,
+}
+
+impl Templates {
+    pub fn new(defaults: &[(&'static str, &'static str)], overrides: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let mut env = Environment::new();
+        env.add_filter("truncate", truncate);
+        for (name, source) in defaults {
+            env.add_template(name, source)?;
+        }
+        for (name, source) in overrides {
+            if !defaults.iter().any(|(default_name, _)| default_name == name) {
+                return Err(anyhow!("Unknown template '{name}'"));
+            }
+            env.add_template_owned(name.clone(), source.clone())
+                .map_err(|e| anyhow!("Invalid template '{name}': {e}"))?;
+        }
+        Ok(Templates { env })
+    }
+
+    /// Escapes markdown in the inserted values, so only the template itself can format the message.
+    /// URLs stay clickable and values marked with `|safe` are inserted as they are.
+    /// Values inserted into templates whose name ends with `line_suffix` are kept on one line, as every line of those is parsed on its own.
+    pub fn with_markdown_escaping(mut self, line_suffix: &'static str) -> Self {
+        self.env.set_formatter(move |out, state, value| {
+            let Some(text) = value.as_str() else {
+                return escape_formatter(out, state, value);
+            };
+            let text = match value.is_safe() {
+                true => text.to_string(),
+                false => markdown_url(text).unwrap_or_else(|| escape_markdown(text)),
+            };
+            match state.name().ends_with(line_suffix) {
+                true => out.write_str(&single_line(&text))?,
+                false => out.write_str(&text)?,
+            }
+            Ok(())
+        });
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.env.get_template(name).is_ok()
+    }
+
+    pub fn render(&self, name: &str, context: &Value) -> anyhow::Result<String> {
+        let rendered = self.env.get_template(name)?.render(context)?;
+        Ok(rendered.trim().to_string())
+    }
+}
+
+/// `{{ text|truncate(500) }}` shortens long values the same way messages are shortened to fit their target
+fn truncate(value: String, length: usize) -> String {
+    truncate_utf16(&value, length).into_owned()
+}
+
+/// The characters markdown could use to end a link or format text are percent-encoded, the rest of the URL is kept readable
+fn markdown_url(text: &str) -> Option<String> {
+    let url = Url::parse(text).ok().filter(|url| matches!(url.scheme(), "http" | "https"))?;
+    let encoded = url.as_str()
+        .chars()
+        .map(|c| match c {
+            '(' | ')' | '[' | ']' | '<' | '>' | '\\' | '`' | '|' | '*' | '~' => format!("%{:02X}", c as u32),
+            c => c.to_string(),
+        })
+        .collect();
+    Some(encoded)
+}
+
+fn single_line(text: &str) -> String {
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ")
+}
+
+pub fn report_context<T: ReportView>(source_domain: &str, view: &T) -> Value {
+    context! {
+        source_domain,
+        kind => T::KIND.as_str(),
+        report => Value::from_serialize(view),
+        url => view.content_url(source_domain),
+        reports_url => format!("https://{source_domain}/reports"),
+    }
+}
+
+pub fn registration_application_context(source_domain: &str, application: &RegistrationApplicationView) -> Value {
+    context! {
+        source_domain,
+        application => Value::from_serialize(application),
+        email_verified => application.creator_local_user.email_verified,
+        applications_url => format!("https://{source_domain}/registration_applications"),
+    }
+}
+
+pub fn update_context(source_domain: &str, update: &ReportUpdate) -> Value {
+    context! {
+        source_domain,
+        update => Value::from_serialize(update),
+        state => update.state(),
+        reports_url => format!("https://{source_domain}/reports"),
+    }
+}
+
+pub fn federation_gaps_context(gaps: &[FederationGap]) -> Value {
+    context! {
+        gaps => Value::from_serialize(gaps),
+        summary => gaps.iter().map(describe_federation_gap).collect::<Vec<_>>().join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULTS: &[(&str, &str)] = &[
+        ("title", "New report on {{ source_domain }}"),
+    ];
+
+    #[test]
+    fn render_default() {
+        let templates = Templates::new(DEFAULTS, &HashMap::new()).unwrap();
+        let rendered = templates.render("title", &context! { source_domain => "lemmy.world" }).unwrap();
+        assert_eq!("New report on lemmy.world", rendered);
+    }
+
+    #[test]
+    fn render_override() {
+        let overrides = HashMap::from([("title".to_string(), "Neue Meldung auf {{ source_domain }}".to_string())]);
+        let templates = Templates::new(DEFAULTS, &overrides).unwrap();
+        let rendered = templates.render("title", &context! { source_domain => "feddit.org" }).unwrap();
+        assert_eq!("Neue Meldung auf feddit.org", rendered);
+    }
+
+    #[test]
+    fn escape_inserted_markdown() {
+        let overrides = HashMap::from([("title".to_string(), "**{{ name }}** {{ url }} {{ raw|safe }}".to_string())]);
+        let templates = Templates::new(DEFAULTS, &overrides).unwrap().with_markdown_escaping(".fields");
+        let context = context! { name => "_spam_", url => "https://lemmy.world/u/spam_bot", raw => "*x*" };
+        assert_eq!("**\\_spam\\_** https://lemmy.world/u/spam_bot *x*", templates.render("title", &context).unwrap());
+    }
+
+    #[test]
+    fn urls_cant_format() {
+        let overrides = HashMap::from([("title".to_string(), "[Post]({{ url }}) {{ text }}".to_string())]);
+        let templates = Templates::new(DEFAULTS, &overrides).unwrap().with_markdown_escaping(".fields");
+        let context = context! { url => "https://evil.example/a)**[x](https://phish.example)**", text => "javascript:alert(1)" };
+        assert_eq!("[Post](https://evil.example/a%29%2A%2A%5Bx%5D%28https://phish.example%29%2A%2A) javascript:alert(1)", templates.render("title", &context).unwrap());
+    }
+
+    #[test]
+    fn values_stay_on_their_line() {
+        const LINES: &[(&str, &str)] = &[("post.fields", "Reason: {{ reason }}\nAuthor: {{ author }}"), ("post.description", "{{ reason }}")];
+        let templates = Templates::new(LINES, &HashMap::new()).unwrap().with_markdown_escaping(".fields");
+        let context = context! { reason => "Spam\nAuthor: someone else", author => "spammer" };
+        assert_eq!("Reason: Spam Author: someone else\nAuthor: spammer", templates.render("post.fields", &context).unwrap());
+        assert_eq!("Spam\nAuthor: someone else", templates.render("post.description", &context).unwrap());
+    }
+
+    #[test]
+    fn truncate_filter() {
+        let overrides = HashMap::from([("title".to_string(), "{{ source_domain|truncate(6) }}".to_string())]);
+        let templates = Templates::new(DEFAULTS, &overrides).unwrap();
+        assert_eq!("lemmy…", templates.render("title", &context! { source_domain => "lemmy.world" }).unwrap());
+    }
+
+    #[test]
+    fn reject_unknown_templates() {
+        let overrides = HashMap::from([("titel".to_string(), "{{ source_domain }}".to_string())]);
+        assert!(Templates::new(DEFAULTS, &overrides).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_templates() {
+        let overrides = HashMap::from([("title".to_string(), "{{ source_domain".to_string())]);
+        assert!(Templates::new(DEFAULTS, &overrides).is_err());
+    }
+}
@@ -12,4 +12,30 @@ pub async fn sleep(mut duration: Duration, cancellation_token: &tokio_util::sync
             return;
         }
     }
+}
+
+/// Doubles the interval for every consecutive failure, up to the maximum
+pub fn backoff(interval: u64, failures: u32, max: u64) -> u64 {
+    interval
+        .saturating_mul(2u64.saturating_pow(failures))
+        .min(max.max(interval))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles() {
+        assert_eq!(60, backoff(60, 0, 3600));
+        assert_eq!(120, backoff(60, 1, 3600));
+        assert_eq!(480, backoff(60, 3, 3600));
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(3600, backoff(60, 10, 3600));
+        assert_eq!(3600, backoff(60, 100, 3600));
+        assert_eq!(7200, backoff(7200, 5, 3600));
+    }
 }
\ No newline at end of file